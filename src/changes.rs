@@ -0,0 +1,261 @@
+//! Module keeping track of what changed between reloads of a dataset
+//!
+//! Every reload that changes the rows served bumps the dataset's `generation` and records the
+//! added, removed and modified keys. Downstream caches can sync incrementally by asking for the
+//! changes since the generation they have seen last, see `handler::changes`.
+//!
+//! Generations are counted within an `epoch`, the time the feed was started. A restart without a
+//! snapshot to continue from starts a new epoch, so clients can't mistake its generations for the
+//! ones they saw before.
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::model::{Key, Record};
+
+/// What changed in a dataset from the previous generation to `generation`
+#[derive(Debug, Serialize)]
+pub struct Changeset {
+    pub generation: u64,
+    pub changed_at: DateTime<Utc>,
+    pub added: Vec<Key>,
+    pub removed: Vec<Key>,
+    pub modified: Vec<Modified>,
+}
+
+/// A key whose row changed, along with the fields that differ
+#[derive(Debug, Serialize)]
+pub struct Modified {
+    pub key: Key,
+    pub fields: Vec<String>,
+}
+
+/// The current generation of a dataset and the latest changesets leading up to it
+#[derive(Clone, Debug)]
+pub struct Changes {
+    /// milliseconds since the Unix epoch when the feed was started
+    pub epoch: u64,
+    pub generation: u64,
+    log: VecDeque<Arc<Changeset>>,
+}
+
+/// A new feed, starting at generation 0 of the current epoch
+impl Default for Changes {
+    fn default() -> Self {
+        Changes::new(Utc::now().timestamp_millis() as u64, 0)
+    }
+}
+
+impl Changes {
+    /// Continue the feed at `generation` of `epoch`, e.g. from a snapshot
+    pub fn new(epoch: u64, generation: u64) -> Self {
+        Changes {
+            epoch,
+            generation,
+            log: VecDeque::new(),
+        }
+    }
+
+    /// Record the changes from the `old` to the `new` rows as the next generation, keeping at
+    /// most `keep` changesets. Nothing is recorded if the rows are the same.
+    pub fn record(
+        &mut self,
+        old: &HashMap<Key, Record>,
+        new: &HashMap<Key, Record>,
+        keep: usize,
+    ) -> Option<Arc<Changeset>> {
+        let mut added: Vec<Key> = new
+            .keys()
+            .filter(|key| !old.contains_key(*key))
+            .cloned()
+            .collect();
+        let mut removed: Vec<Key> = old
+            .keys()
+            .filter(|key| !new.contains_key(*key))
+            .cloned()
+            .collect();
+        let mut modified: Vec<Modified> = new
+            .iter()
+            .filter_map(|(key, record)| {
+                let fields = fields(old.get(key)?, record);
+                if fields.is_empty() {
+                    None
+                } else {
+                    Some(Modified {
+                        key: key.clone(),
+                        fields,
+                    })
+                }
+            })
+            .collect();
+        if added.is_empty() && removed.is_empty() && modified.is_empty() {
+            return None;
+        }
+        added.sort();
+        removed.sort();
+        modified.sort_by(|a, b| a.key.cmp(&b.key));
+
+        self.generation += 1;
+        let changeset = Arc::new(Changeset {
+            generation: self.generation,
+            changed_at: Utc::now(),
+            added,
+            removed,
+            modified,
+        });
+        self.log.push_back(changeset.clone());
+        while self.log.len() > keep {
+            self.log.pop_front();
+        }
+        Some(changeset)
+    }
+
+    /// The changesets after `generation` of `epoch`, in order. Returns `None` if they are not all
+    /// known anymore, or `generation` is one we never had, e.g. from before a restart. The client
+    /// has to fetch everything again then.
+    pub fn since(&self, epoch: u64, generation: u64) -> Option<Vec<Arc<Changeset>>> {
+        if epoch != self.epoch || generation > self.generation {
+            return None;
+        }
+        if generation == self.generation {
+            return Some(Vec::new());
+        }
+        match self.log.front() {
+            Some(oldest) if oldest.generation <= generation + 1 => Some(
+                self.log
+                    .iter()
+                    .filter(|changeset| changeset.generation > generation)
+                    .cloned()
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// The fields whose values differ between two versions of a row, including the ones only one of
+/// them has
+fn fields(old: &Record, new: &Record) -> Vec<String> {
+    let mut fields: Vec<String> = new
+        .iter()
+        .filter(|&(field, value)| old.get(field) != Some(value))
+        .map(|(field, _)| field.clone())
+        .collect();
+    fields.extend(
+        old.keys()
+            .filter(|field| !new.contains_key(*field))
+            .cloned(),
+    );
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn rows(rows: Value) -> HashMap<Key, Record> {
+        rows.as_object()
+            .unwrap()
+            .iter()
+            .map(|(key, row)| (vec![key.clone()], row.as_object().unwrap().clone()))
+            .collect()
+    }
+
+    fn generations(changesets: &[Arc<Changeset>]) -> Vec<u64> {
+        changesets
+            .iter()
+            .map(|changeset| changeset.generation)
+            .collect()
+    }
+
+    #[test]
+    fn records_added_removed_and_modified_keys() {
+        let old = rows(json!({
+            "1": { "name": "a", "price": 1 },
+            "2": { "name": "b" },
+            "3": { "name": "c", "color": "red" },
+            "4": { "name": "d" },
+        }));
+        let new = rows(json!({
+            "1": { "name": "a", "price": 2 },
+            "3": { "name": "c" },
+            "4": { "name": "d" },
+            "5": { "name": "e" },
+        }));
+        let mut changes = Changes::new(1, 0);
+
+        let changeset = changes.record(&old, &new, 10).unwrap();
+        assert_eq!(changeset.generation, 1);
+        assert_eq!(changeset.added, vec![vec!["5".to_string()]]);
+        assert_eq!(changeset.removed, vec![vec!["2".to_string()]]);
+        let modified: Vec<(&Key, &Vec<String>)> = changeset
+            .modified
+            .iter()
+            .map(|modified| (&modified.key, &modified.fields))
+            .collect();
+        assert_eq!(
+            modified,
+            vec![
+                (&vec!["1".to_string()], &vec!["price".to_string()]),
+                (&vec!["3".to_string()], &vec!["color".to_string()]),
+            ]
+        );
+        assert_eq!(changes.generation, 1);
+    }
+
+    #[test]
+    fn records_nothing_for_the_same_rows() {
+        let old = rows(json!({ "1": { "name": "a" } }));
+        let mut changes = Changes::new(1, 3);
+        assert!(changes.record(&old, &old.clone(), 10).is_none());
+        assert_eq!(changes.generation, 3);
+        assert_eq!(changes.since(1, 3).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn serves_the_changes_kept() {
+        let mut changes = Changes::new(1, 0);
+        let mut old = rows(json!({}));
+        for i in 0..5 {
+            let new = rows(json!({ i.to_string(): { "name": "a" } }));
+            changes.record(&old, &new, 3);
+            old = new;
+        }
+        assert_eq!(changes.generation, 5);
+
+        // generations 3 to 5 are kept, so clients at 2 or later can catch up
+        assert_eq!(generations(&changes.since(1, 2).unwrap()), vec![3, 4, 5]);
+        assert_eq!(generations(&changes.since(1, 4).unwrap()), vec![5]);
+        assert_eq!(
+            generations(&changes.since(1, 5).unwrap()),
+            Vec::<u64>::new()
+        );
+        assert!(changes.since(1, 1).is_none());
+        assert!(changes.since(1, 0).is_none());
+    }
+
+    #[test]
+    fn refuses_generations_of_other_epochs() {
+        let mut changes = Changes::new(1, 0);
+        changes.record(&rows(json!({})), &rows(json!({ "1": {} })), 10);
+        assert!(changes.since(1, 0).is_some());
+        assert!(changes.since(2, 0).is_none());
+        assert!(changes.since(2, 1).is_none());
+    }
+
+    #[test]
+    fn refuses_generations_we_never_had() {
+        let mut changes = Changes::new(1, 0);
+        changes.record(&rows(json!({})), &rows(json!({ "1": {} })), 10);
+        assert!(changes.since(1, 2).is_none());
+
+        // continued from a snapshot, without the changesets leading up to it
+        let changes = Changes::new(1, 7);
+        assert!(changes.since(1, 7).is_some());
+        assert!(changes.since(1, 6).is_none());
+        assert!(changes.since(1, 8).is_none());
+    }
+}
@@ -0,0 +1,128 @@
+//! Module for transparently decompressing csv sources
+//!
+//! The compression is taken from the file extension or the HTTP headers if they tell us, and
+//! sniffed from the magic bytes at the start of the data otherwise.
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+use bzip2::read::BzDecoder;
+use flate2::read::MultiGzDecoder;
+use zstd;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Bzip2,
+}
+
+impl Compression {
+    /// Detect the compression from the extension of a path or URL
+    pub fn from_path(path: &str) -> Option<Self> {
+        // ignore query strings and fragments of URLs
+        let path = path.split(|c| c == '?' || c == '#').next().unwrap_or(path);
+        let extension = Path::new(path).extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "gz" | "gzip" => Some(Compression::Gzip),
+            "zst" | "zstd" => Some(Compression::Zstd),
+            "bz2" | "bzip2" => Some(Compression::Bzip2),
+            _ => None,
+        }
+    }
+
+    /// Detect the compression from a `Content-Encoding` or `Content-Type` header value
+    pub fn from_header(value: &str) -> Option<Self> {
+        let mime = value.split(';').next().unwrap_or(value).trim().to_lowercase();
+        match mime.as_str() {
+            "gzip" | "x-gzip" | "application/gzip" | "application/x-gzip" => {
+                Some(Compression::Gzip)
+            }
+            "zstd" | "application/zstd" => Some(Compression::Zstd),
+            "bzip2" | "x-bzip2" | "application/x-bzip2" => Some(Compression::Bzip2),
+            _ => None,
+        }
+    }
+
+    /// Detect the compression from the first bytes of the data
+    pub fn from_magic(bytes: &[u8]) -> Self {
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else if bytes.starts_with(b"BZh") {
+            Compression::Bzip2
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// Wrap `rdr` in the matching decoder. If no `hint` is given the compression is sniffed from the
+/// magic bytes.
+pub fn decompress<'a, R: Read + 'a>(
+    rdr: R,
+    hint: Option<Compression>,
+) -> io::Result<Box<dyn Read + 'a>> {
+    let mut rdr = BufReader::new(rdr);
+    let compression = match hint {
+        Some(compression) => compression,
+        None => Compression::from_magic(rdr.fill_buf()?),
+    };
+
+    Ok(match compression {
+        Compression::None => Box::new(rdr),
+        Compression::Gzip => Box::new(MultiGzDecoder::new(rdr)),
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::with_buffer(rdr)?),
+        Compression::Bzip2 => Box::new(BzDecoder::new(rdr)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_the_compression_from_paths() {
+        assert_eq!(Compression::from_path("data.csv.gz"), Some(Compression::Gzip));
+        assert_eq!(Compression::from_path("data.csv.GZIP"), Some(Compression::Gzip));
+        assert_eq!(Compression::from_path("/srv/data.zst"), Some(Compression::Zstd));
+        assert_eq!(Compression::from_path("data.csv.bz2"), Some(Compression::Bzip2));
+        assert_eq!(
+            Compression::from_path("https://example.com/data.csv.gz?token=a.b#c.d"),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(Compression::from_path("data.csv"), None);
+        assert_eq!(Compression::from_path("data"), None);
+        assert_eq!(Compression::from_path("https://example.com/data.csv?file=a.gz"), None);
+    }
+
+    #[test]
+    fn detects_the_compression_from_headers() {
+        assert_eq!(Compression::from_header("gzip"), Some(Compression::Gzip));
+        assert_eq!(
+            Compression::from_header("Application/X-Gzip; charset=binary"),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(Compression::from_header(" zstd "), Some(Compression::Zstd));
+        assert_eq!(
+            Compression::from_header("application/x-bzip2"),
+            Some(Compression::Bzip2)
+        );
+        assert_eq!(Compression::from_header("text/csv; charset=utf-8"), None);
+        assert_eq!(Compression::from_header("identity"), None);
+    }
+
+    #[test]
+    fn detects_the_compression_from_magic_bytes() {
+        assert_eq!(Compression::from_magic(&[0x1f, 0x8b, 0x08]), Compression::Gzip);
+        assert_eq!(
+            Compression::from_magic(&[0x28, 0xb5, 0x2f, 0xfd, 0x00]),
+            Compression::Zstd
+        );
+        assert_eq!(Compression::from_magic(b"BZh91AY"), Compression::Bzip2);
+        assert_eq!(Compression::from_magic(b"id,name\n"), Compression::None);
+        assert_eq!(Compression::from_magic(&[0x1f]), Compression::None);
+        assert_eq!(Compression::from_magic(&[]), Compression::None);
+    }
+}
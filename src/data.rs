@@ -1,94 +1,851 @@
+use std::collections::hash_map::{DefaultHasher, Entry};
 use std::collections::HashMap;
-use std::fs::File;
+use std::hash::Hasher;
 use std::io;
 use std::io::prelude::*;
 use std::sync::{Arc, Mutex};
 
+use crate::changes::Changes;
+use crate::compression::decompress;
+use crate::encoding;
 use crate::error::Error;
-use crate::model::{error_product, Product};
-use crate::settings::Settings;
+use crate::format;
+use crate::join;
+use crate::model::{Key, Product, Record};
+use crate::report::{FileStats, ImportReport};
+use crate::schema::{ConversionError, Schema};
+use crate::settings::{Csv, Duplicates, Mode, Settings};
+use crate::snapshot;
+use crate::source::{for_uri, DataSource, Fetched, Validators};
 
-use reqwest;
+use serde_json::Value;
 
 /// type alias for `AppState`
 pub type StateType = Arc<Mutex<AppState>>;
 
 #[derive(Debug)]
-/// The `AppState` is constructed with the app configuration and the generated `HashMap` from the
-/// csv data.
+/// The `AppState` is constructed with the app configuration and the generated `HashMap`s from
+/// the csv data of every configured dataset, keyed by the dataset's name.
 pub struct AppState {
     pub settings: Settings,
-    pub map: HashMap<usize, Product>,
+    pub datasets: HashMap<String, Dataset>,
 }
 
 /// `AppState` implements a `new` function for convenience.
-/// Note that this should only be done once. Subsequent updates to the `map` should be done via
-/// mutable references to the `Mutex` inside of the `Arc`.
+/// Note that this should only be done once. Subsequent updates to the datasets should be done
+/// via `swap` on the `Dataset` inside of the `Mutex`.
 impl AppState {
     pub fn new() -> Result<StateType, Error> {
         let settings = Settings::new()?;
         println!("{:?}", &settings);
-        let csv = get_csv(&settings)?;
-        let map = parse_csv(&settings, csv)?;
 
-        Ok(Arc::new(Mutex::new(AppState { settings, map })))
+        let mut datasets = HashMap::new();
+        for source in settings.datasets()? {
+            datasets.insert(source.name.clone(), Dataset::open(source)?);
+        }
+        let joined: Vec<String> = datasets
+            .values()
+            .filter(|dataset| !dataset.csv.joins.is_empty())
+            .map(|dataset| dataset.csv.name.clone())
+            .collect();
+        for name in &joined {
+            apply_joins(&mut datasets, name);
+        }
+
+        Ok(Arc::new(Mutex::new(AppState { settings, datasets })))
     }
 }
 
-/// Retrieve the csv either from a local file, or try to fetch it, from an external service
-fn get_csv(settings: &Settings) -> Result<String, Error> {
-    if settings.is_local() {
-        let mut file = File::open(&settings.csv.uri)?;
-        let mut s = String::new();
-        file.read_to_string(&mut s)?;
-        Ok(s)
-    } else {
-        let data = fetch_data(&settings)?;
-        Ok(data)
+#[derive(Clone, Debug)]
+/// A single dataset, i.e. its configuration, the generated `HashMap` and the report of the import
+/// it was generated by. If the latest import was refused by the safety gates, its report is kept
+/// in `latest` while `report` still describes the data being served.
+///
+/// The `map` lives behind its own `Arc` so handlers only need to hold the lock for as long as it
+/// takes to clone the pointer, while a reload can build a fresh map without holding it at all.
+/// `raw` holds the rows as parsed from the source, `map` the rows served, i.e. with the `joins`
+/// applied. Without joins both point to the same map.
+pub struct Dataset {
+    pub csv: Csv,
+    pub data_source: Arc<dyn DataSource>,
+    pub raw: Arc<HashMap<Key, Record>>,
+    pub map: Arc<HashMap<Key, Record>>,
+    pub report: Arc<ImportReport>,
+    pub latest: Arc<ImportReport>,
+    pub validators: Validators,
+    /// generation of the served `map` and the latest changes leading up to it
+    pub changes: Changes,
+    /// whether the data was restored from a snapshot and not yet refreshed from the source
+    pub stale: bool,
+    /// generation last written to the snapshot, shared by all copies of the dataset
+    persisted: Arc<Mutex<Option<u64>>>,
+}
+
+impl Dataset {
+    /// Load a dataset on startup from the `DataSource` matching its `uri`
+    pub fn open(source: Csv) -> Result<Dataset, Error> {
+        let data_source = for_uri(&source)?;
+        Dataset::open_with(source, data_source)
+    }
+
+    /// Load a dataset on startup from the given `data_source`. If it has a snapshot we serve that
+    /// right away and leave it to the caller to refresh the `stale` data in the background.
+    pub fn open_with(source: Csv, data_source: Arc<dyn DataSource>) -> Result<Dataset, Error> {
+        if let Some(path) = &source.snapshot {
+            match snapshot::read(path, source.fingerprint()) {
+                Ok(Some(snapshot)) => {
+                    println!(
+                        "Serving {} rows of {} from snapshot {}",
+                        snapshot.map.len(),
+                        source.name,
+                        path
+                    );
+                    let report = Arc::new(snapshot.report);
+                    let map = Arc::new(snapshot.map);
+                    return Ok(Dataset {
+                        csv: source,
+                        data_source,
+                        raw: map.clone(),
+                        map,
+                        report: report.clone(),
+                        latest: report,
+                        validators: snapshot.validators,
+                        changes: Changes::new(snapshot.epoch, snapshot.generation),
+                        stale: true,
+                        persisted: Arc::new(Mutex::new(Some(snapshot.generation))),
+                    });
+                }
+                Ok(None) => {}
+                Err(e) => eprintln!("Ignoring snapshot {}: {}", path, e),
+            }
+        }
+
+        let (import, validators) = match load(&source, &*data_source, &Validators::default())? {
+            (Some(import), validators) => (import, validators),
+            // there is nothing to compare against on startup, yet a `DataSource` may say so
+            (None, _) => {
+                return Err(Error::Other(format!(
+                    "Dataset {}: the source reported no changes on the initial load",
+                    source.name
+                )))
+            }
+        };
+        source.gates.check(None, &import).map_err(Error::Refused)?;
+
+        let report = Arc::new(import.report);
+        let map = Arc::new(import.map);
+        let dataset = Dataset {
+            csv: source,
+            data_source,
+            raw: map.clone(),
+            map,
+            report: report.clone(),
+            latest: report,
+            validators,
+            changes: Changes::default(),
+            stale: false,
+            persisted: Arc::new(Mutex::new(None)),
+        };
+        dataset.persist();
+        Ok(dataset)
+    }
+
+    /// Write the dataset to its snapshot, if it has one configured. Failing to do so is not
+    /// fatal, we still have the data in memory.
+    ///
+    /// Reloads persist outside the lock of the `AppState`, so writes of the same dataset are
+    /// serialized here, and skipped if a newer generation has been written already.
+    pub fn persist(&self) {
+        if let Some(path) = &self.csv.snapshot {
+            let mut persisted = self.persisted.lock().unwrap();
+            if persisted.map_or(false, |generation| generation > self.changes.generation) {
+                return;
+            }
+            match snapshot::write(
+                path,
+                self.csv.fingerprint(),
+                &self.raw,
+                &self.report,
+                &self.validators,
+                self.changes.epoch,
+                self.changes.generation,
+            ) {
+                Ok(()) => *persisted = Some(self.changes.generation),
+                Err(e) => eprintln!("Writing snapshot {} failed: {}", path, e),
+            }
+        }
     }
+
+    /// Replace the current `map` with freshly parsed `raw` rows. Requests that already cloned the
+    /// previous `Arc` keep on reading from it until they are done.
+    ///
+    /// Datasets with `joins` serve the raw rows until they are joined again, see `reload`.
+    pub fn swap(&mut self, raw: Arc<HashMap<Key, Record>>, report: Arc<ImportReport>) -> Reloaded {
+        let added = raw.keys().filter(|id| !self.raw.contains_key(*id)).count();
+        let removed = self.raw.keys().filter(|id| !raw.contains_key(*id)).count();
+        let rows = raw.len();
+        self.raw = raw;
+        self.map = self.raw.clone();
+        self.report = report;
+        self.latest = self.report.clone();
+        self.stale = false;
+        Reloaded {
+            rows,
+            added,
+            removed,
+        }
+    }
+}
+
+/// Summary of a reload, i.e. how the new map differs from the previous one
+#[derive(Debug)]
+pub struct Reloaded {
+    pub rows: usize,
+    pub added: usize,
+    pub removed: usize,
 }
 
-/// Parse the csv, deserializing it with `serde` based on the `Product` struct
-pub fn parse_csv(settings: &Settings, data: String) -> io::Result<HashMap<usize, Product>> {
-    let mut map = HashMap::new();
+/// Load the dataset called `name` again and swap it into the `AppState` if that succeeded and
+/// the import passes the dataset's safety gates.
+///
+/// The lock is neither held while fetching and parsing, nor while joining and diffing the
+/// datasets affected by the reload. Those are joined and diffed on copies, which are only swapped
+/// in if no other reload replaced any of the rows they were built from in the meantime.
+/// Otherwise they are built again.
+///
+/// Returns `None` if the source did not change since the last load.
+pub fn reload(state: &StateType, name: &str) -> Result<Option<Reloaded>, Error> {
+    let (source, data_source, validators) = {
+        let state = state.lock().unwrap();
+        let dataset = state
+            .datasets
+            .get(name)
+            .ok_or_else(|| Error::Other(format!("Unknown dataset {}", name)))?;
+        (
+            dataset.csv.clone(),
+            dataset.data_source.clone(),
+            dataset.validators.clone(),
+        )
+    };
 
-    let mut rdr = csv::ReaderBuilder::new()
-        // FIXME this can panic if an empty string is provided as delimiter
-        .delimiter(settings.csv.delimiter.clone().into_bytes()[0])
-        .from_reader(data.as_bytes());
+    let (mut import, validators) = match load(&source, &*data_source, &validators)? {
+        (Some(import), validators) => (import, validators),
+        (None, validators) => {
+            // the same content may come with a new ETag or Last-Modified, remember those
+            let mut state = state.lock().unwrap();
+            let dataset = state.datasets.get_mut(name).unwrap();
+            dataset.validators = validators;
+            dataset.stale = false;
+            return Ok(None);
+        }
+    };
 
-    for result in rdr.deserialize() {
-        // Notice that we need to provide a type hint for automatic deserialization. And by
-        // needing to do that, we seem to have no idiomatic way of skipping bogus lines :(
-        // Hence we print out the error here for now and return a dummy Product that is used to
-        // continue the loop
-        let record: Product = result.unwrap_or_else(|e| {
-            eprintln!("{}", e);
-            error_product()
+    {
+        let mut state = state.lock().unwrap();
+        // the dataset can't vanish, the datasets are only ever inserted on startup
+        let dataset = state.datasets.get_mut(name).unwrap();
+        if let Err(reason) = source.gates.check(Some(&*dataset), &import) {
+            // keep the report around so the refused import can be inspected
+            import.report.refused = Some(reason.clone());
+            dataset.latest = Arc::new(import.report);
+            return Err(Error::Refused(reason));
+        }
+    }
+    let raw = Arc::new(import.map);
+    let report = Arc::new(import.report);
+
+    loop {
+        // cheap copies of the datasets whose served rows change, i.e. this one and the ones
+        // joining it, along with the datasets joined by those
+        let mut copies = involved(&state.lock().unwrap().datasets, name);
+        let before = copies.clone();
+        let affected: Vec<String> = copies
+            .values()
+            .filter(|dataset| {
+                dataset.csv.name == name
+                    || dataset.csv.joins.iter().any(|join| join.dataset == name)
+            })
+            .map(|dataset| dataset.csv.name.clone())
+            .collect();
+
+        let dataset = copies.get_mut(name).unwrap();
+        dataset.validators = validators.clone();
+        let reloaded = dataset.swap(raw.clone(), report.clone());
+
+        // join the fresh rows onto the datasets joining this one, and ours onto them, then
+        // record what changed for the changes feed
+        let mut changesets = Vec::new();
+        for name in &affected {
+            if !copies[name].csv.joins.is_empty() {
+                apply_joins(&mut copies, name);
+            }
+            let copy = copies.get_mut(name).unwrap();
+            let keep = copy.csv.keep_changes;
+            if let Some(changeset) = copy.changes.record(&before[name].map, &copy.map, keep) {
+                changesets.push((name, changeset));
+            }
+        }
+
+        // start over if another reload swapped any of the rows we joined or diffed in the meantime
+        let mut state = state.lock().unwrap();
+        let changed = before.iter().any(|(name, copy)| {
+            let dataset = &state.datasets[name];
+            !Arc::ptr_eq(&dataset.raw, &copy.raw) || !Arc::ptr_eq(&dataset.map, &copy.map)
         });
-        if record.id == 0 {
+        if changed {
             continue;
-        };
-        map.insert(record.id, record);
-    }
-    Ok(map)
-}
-
-/// Fetch csv data from an external service and return it as a `String`
-pub fn fetch_data(settings: &Settings) -> Result<String, Error> {
-    println!("Fetching data from {}", settings.csv.uri);
-    let client = match &settings.csv.username {
-        Some(username) => {
-            if let Some(password) = &settings.csv.password {
-                reqwest::Client::new()
-                    .get(&settings.csv.uri)
-                    .basic_auth(username, Some(password))
-            } else {
-                return Err(Error::Other("Need password for Basic Auth".into()));
+        }
+
+        for name in &affected {
+            let copy = &copies[name];
+            let dataset = state.datasets.get_mut(name).unwrap();
+            dataset.map = copy.map.clone();
+            dataset.changes = copy.changes.clone();
+            if dataset.csv.name == source.name {
+                dataset.raw = copy.raw.clone();
+                dataset.report = copy.report.clone();
+                dataset.latest = copy.latest.clone();
+                dataset.validators = copy.validators.clone();
+                dataset.stale = false;
+            }
+        }
+        drop(state);
+
+        for (name, changeset) in changesets {
+            println!(
+                "Generation {} of {}: {} added, {} removed, {} modified",
+                changeset.generation,
+                name,
+                changeset.added.len(),
+                changeset.removed.len(),
+                changeset.modified.len()
+            );
+        }
+        // write the snapshots from the (cheap) copies, so requests don't have to wait for the
+        // disk. The generation is persisted, so clients aren't told they are up to date with a
+        // generation they never saw after a restart.
+        for name in &affected {
+            copies[name].persist();
+        }
+        return Ok(Some(reloaded));
+    }
+}
+
+/// Copies of the dataset called `name`, the datasets joining it and the ones joined by those
+fn involved(datasets: &HashMap<String, Dataset>, name: &str) -> HashMap<String, Dataset> {
+    let mut names: Vec<&str> = vec![name];
+    for dataset in datasets.values() {
+        if dataset.csv.joins.iter().any(|join| join.dataset == name) {
+            names.push(&dataset.csv.name);
+        }
+    }
+    let joined: Vec<&str> = names
+        .iter()
+        .flat_map(|name| datasets[*name].csv.joins.iter())
+        .map(|join| join.dataset.as_str())
+        .collect();
+    names.extend(joined);
+    names
+        .into_iter()
+        .map(|name| (name.to_string(), datasets[name].clone()))
+        .collect()
+}
+
+/// Replace the served `map` of the dataset called `name` by its raw rows with its `joins` applied
+fn apply_joins(datasets: &mut HashMap<String, Dataset>, name: &str) {
+    let map = join::apply(&datasets[name], datasets);
+    datasets.get_mut(name).unwrap().map = Arc::new(map);
+}
+
+/// Retrieve the raw data from the `data_source` and parse it into a fresh map, unless it is
+/// unchanged according to the `validators` of the previous load. Returns the import, if any,
+/// along with the validators to remember for the next load.
+///
+/// The data is parsed while it is being read, so the content hash is only known afterwards. If
+/// it matches the previous one the freshly parsed map is thrown away again, but the validators
+/// the source sent along with it are kept.
+pub fn load(
+    source: &Csv,
+    data_source: &dyn DataSource,
+    validators: &Validators,
+) -> Result<(Option<Import>, Validators), Error> {
+    match data_source.open(validators)? {
+        Fetched::Modified(bodies, mut next) => {
+            let mut hasher = DefaultHasher::new();
+            let mut import = Import::new(source);
+            for (i, body) in bodies.into_iter().enumerate() {
+                let mut rdr = decompress(body.rdr, body.compression)?;
+                if source.format.is_text() {
+                    rdr = encoding::decode(rdr, source.encoding.as_ref().map(String::as_str))?;
+                }
+                let mut rdr = HashingReader::new(rdr);
+
+                let report = &import.report;
+                let (headers, rows, accepted, rejected, duplicates) = (
+                    report.headers.clone(),
+                    report.rows,
+                    report.accepted,
+                    report.rejected.len(),
+                    report.duplicates.len(),
+                );
+                format::parse(source, &mut rdr, &mut import).map_err(|e| match e.kind() {
+                    _ if !data_source.is_remote() => Error::Io(e),
+                    // the body is streamed, so a read timing out surfaces only while parsing
+                    io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => {
+                        Error::Timeout(e.to_string())
+                    }
+                    _ => Error::Body(e),
+                })?;
+                hasher.write_u64(rdr.finish());
+
+                // datasets split into several files are merged in order, into the same map
+                let report = &mut import.report;
+                if i > 0 && report.headers != headers {
+                    return Err(Error::Other(format!(
+                        "header of {} differs from the first file: {:?} instead of {:?}",
+                        body.name, report.headers, headers
+                    )));
+                }
+                for rejection in &mut report.rejected[rejected..] {
+                    rejection.file = Some(body.name.clone());
+                }
+                for duplicate in &mut report.duplicates[duplicates..] {
+                    duplicate.file = Some(body.name.clone());
+                }
+                let stats = FileStats {
+                    file: body.name,
+                    rows: report.rows - rows,
+                    accepted: report.accepted - accepted,
+                    rejected: report.rejected.len() - rejected,
+                    duplicates: report.duplicates.len() - duplicates,
+                };
+                report.files.push(stats);
+            }
+            let hash = hasher.finish();
+            let unchanged = validators.hash == Some(hash);
+            next.hash = Some(hash);
+            if unchanged {
+                return Ok((None, next));
             }
+            println!("{}", import.report.summary());
+            Ok((Some(import), next))
         }
-        None => reqwest::Client::new().get(&settings.csv.uri),
+        Fetched::NotModified => Ok((None, validators.clone())),
+    }
+}
+
+/// Insert a parsed row into the `map` of an import, resolving a key that is taken already
+/// according to the dataset's `duplicates` policy. Every duplicate is listed in the `report`.
+///
+/// Returns whether the row was accepted as a new key, so that `accepted` matches the number of
+/// rows served. Duplicates are not, even if they replace or are merged into the row taken
+/// already. Fails with the reason to fail the whole load.
+pub fn insert(
+    import: &mut Import,
+    policy: Duplicates,
+    line: u64,
+    key: Key,
+    record: Record,
+) -> Result<bool, String> {
+    let mut entry = match import.map.entry(key) {
+        Entry::Vacant(entry) => {
+            entry.insert(record);
+            return Ok(true);
+        }
+        Entry::Occupied(entry) => entry,
     };
-    let resp = client.send()?.text()?;
-    Ok(resp)
+
+    import.report.duplicate(line, entry.key());
+    match policy {
+        Duplicates::First => {}
+        Duplicates::Last => {
+            entry.insert(record);
+        }
+        Duplicates::Merge => {
+            let existing = entry.get_mut();
+            for (column, value) in record {
+                let empty = match &value {
+                    Value::Null => true,
+                    Value::String(value) => value.is_empty(),
+                    _ => false,
+                };
+                if !empty {
+                    existing.insert(column, value);
+                }
+            }
+        }
+        Duplicates::Reject => {
+            import.report.reject(line, None, None, "duplicate key");
+        }
+        Duplicates::Fail => {
+            return Err(format!(
+                "duplicate key {} in line {}",
+                entry.key().join("/"),
+                line
+            ))
+        }
+    }
+    Ok(false)
+}
+
+/// A reader hashing all bytes passing through it, used to skip swapping in a body we have seen
+/// before
+pub struct HashingReader<R> {
+    inner: R,
+    hasher: DefaultHasher,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R) -> Self {
+        HashingReader {
+            inner,
+            hasher: DefaultHasher::new(),
+        }
+    }
+
+    /// The hash of everything read so far
+    pub fn finish(&self) -> u64 {
+        self.hasher.finish()
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.write(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Parse the csv, deserializing it with `serde` based on the `Product` struct, or taking the
+/// columns from the header row in `Mode::Dynamic`, and keying it by the dataset's `key` columns
+///
+/// Rows are deserialized as they are read from `data`, so the raw csv is never held in memory
+/// as a whole. Rows that can't be deserialized are skipped and listed in the `ImportReport`.
+/// The rows are added to `import`, which holds the rows of the earlier files of the dataset, if
+/// it consists of several.
+pub fn parse_csv<R: Read>(source: &Csv, mut data: R, import: &mut Import) -> io::Result<()> {
+    let dialect = &source.dialect;
+    let mut head = Vec::new();
+    let delimiter = dialect.delimiter(&source.delimiter, &mut data, &mut head)?;
+    let mut rdr = dialect
+        .builder(delimiter)
+        .from_reader(io::Cursor::new(head).chain(data));
+
+    let headers = if dialect.columns.is_empty() {
+        rdr.headers().map_err(io_error)?.clone()
+    } else {
+        csv::StringRecord::from(dialect.columns.clone())
+    };
+    import.report.headers = headers.iter().map(String::from).collect();
+    if let Some(schema) = &source.schema {
+        if let Some(column) = schema
+            .keys()
+            .find(|column| !headers.iter().any(|header| header == column.as_str()))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Schema column {} not found in {}", column, source.uri),
+            ));
+        }
+    }
+    let columns = source.key.columns();
+    let key = columns
+        .iter()
+        .map(|column| {
+            headers
+                .iter()
+                .position(|header| header == *column)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Key column {} not found in {}", column, source.uri),
+                    )
+                })
+        })
+        .collect::<io::Result<Vec<usize>>>()?;
+
+    for result in rdr.records() {
+        import.report.rows += 1;
+
+        // Errors reading the underlying stream abort the whole parse, bogus lines are skipped
+        let row = match result {
+            Ok(row) => row,
+            Err(e) => {
+                if let csv::ErrorKind::Io(_) = e.kind() {
+                    return Err(io_error(e));
+                }
+                let line = e.position().map(|pos| pos.line()).unwrap_or_default();
+                import.report.reject(line, None, None, e.to_string());
+                continue;
+            }
+        };
+        let line = row.position().map(|pos| pos.line()).unwrap_or_default();
+
+        // with a `flexible` dialect the row may lack the key columns altogether
+        let id: Key = key
+            .iter()
+            .map(|&i| row.get(i).unwrap_or_default().trim().to_string())
+            .collect();
+        if let Some(i) = id.iter().position(String::is_empty) {
+            import.report.reject(line, Some(columns[i]), row.get(key[i]), "empty key");
+            continue;
+        }
+
+        let record = match source.mode {
+            Mode::Product => row
+                .deserialize::<Product>(Some(&headers))
+                .map(Product::into_record)
+                .map_err(|e| match e.kind() {
+                    // pin the error to the offending column if `csv` tells us which one it is
+                    csv::ErrorKind::Deserialize { err, .. } => {
+                        let field = err.field().map(|field| field as usize);
+                        import.report.reject(
+                            line,
+                            field.and_then(|i| headers.get(i)),
+                            field.and_then(|i| row.get(i)),
+                            err.kind().to_string(),
+                        )
+                    }
+                    _ => import.report.reject(line, None, None, e.to_string()),
+                }),
+            Mode::Dynamic => to_record(&headers, &row, source.schema.as_ref()).map_err(|e| {
+                import.report.reject(line, Some(&e.column), Some(&e.value), e.reason)
+            }),
+        };
+        if let Ok(record) = record {
+            let accepted = insert(import, source.duplicates, line, id, record)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            if accepted {
+                import.report.accepted += 1;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Unwrap the `io::Error` a csv error wraps, so its kind survives, e.g. a read timing out. Going
+/// through `From` would turn every csv error into one of `ErrorKind::Other`.
+fn io_error(e: csv::Error) -> io::Error {
+    if let csv::ErrorKind::Io(_) = e.kind() {
+        if let csv::ErrorKind::Io(e) = e.into_kind() {
+            return e;
+        }
+        unreachable!()
+    }
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// The outcome of parsing a csv, the rows to serve and the report of how we got there
+#[derive(Debug)]
+pub struct Import {
+    pub map: HashMap<Key, Record>,
+    pub report: ImportReport,
+}
+
+impl Import {
+    pub fn new(source: &Csv) -> Self {
+        Import {
+            map: HashMap::new(),
+            report: ImportReport::new(&source.name, &source.uri),
+        }
+    }
+}
+
+/// Build a `Record` from a raw `row`, converting the cells of the columns the `schema` declares
+fn to_record(
+    headers: &csv::StringRecord,
+    row: &csv::StringRecord,
+    schema: Option<&Schema>,
+) -> Result<Record, ConversionError> {
+    let mut record = Record::new();
+    for (column, raw) in headers.iter().zip(row.iter()) {
+        let value = match schema.and_then(|schema| schema.get(column)) {
+            Some(definition) => definition.convert(raw).map_err(|reason| ConversionError {
+                column: column.into(),
+                value: raw.into(),
+                reason,
+            })?,
+            None => Value::String(raw.into()),
+        };
+        record.insert(column.into(), value);
+    }
+    Ok(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::Body;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn source(settings: Value) -> Csv {
+        serde_json::from_value(settings).unwrap()
+    }
+
+    /// Yields a header row and then times out
+    struct TimingOut(bool);
+
+    impl Read for TimingOut {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0 {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "timed out"));
+            }
+            self.0 = true;
+            let header = b"id,name\n";
+            buf[..header.len()].copy_from_slice(header);
+            Ok(header.len())
+        }
+    }
+
+    /// Serves the same rows with a new ETag every time
+    #[derive(Debug, Default)]
+    struct Retagging(AtomicUsize);
+
+    impl DataSource for Retagging {
+        fn open(&self, _validators: &Validators) -> Result<Fetched<Vec<Body>>, Error> {
+            let etag = format!("\"{}\"", self.0.fetch_add(1, Ordering::SeqCst));
+            let body = Body {
+                name: "test.csv".to_string(),
+                rdr: Box::new(&b"id,name\n1,a\n"[..]),
+                compression: None,
+            };
+            let validators = Validators {
+                etag: Some(etag),
+                ..Validators::default()
+            };
+            Ok(Fetched::Modified(vec![body], validators))
+        }
+    }
+
+    /// Never has anything new
+    #[derive(Debug)]
+    struct Unchanged;
+
+    impl DataSource for Unchanged {
+        fn open(&self, _validators: &Validators) -> Result<Fetched<Vec<Body>>, Error> {
+            Ok(Fetched::NotModified)
+        }
+    }
+
+    #[test]
+    fn fails_to_open_without_data() {
+        let source = source(json!({ "uri": "test.csv", "mode": "dynamic", "key": ["id"] }));
+        assert!(Dataset::open_with(source, Arc::new(Unchanged)).is_err());
+    }
+
+    #[test]
+    fn keeps_new_validators_of_unchanged_data() {
+        let source = source(json!({ "uri": "test.csv", "mode": "dynamic", "key": ["id"] }));
+        let data_source = Retagging::default();
+        let (import, first) = load(&source, &data_source, &Validators::default()).unwrap();
+        assert!(import.is_some());
+
+        let (import, second) = load(&source, &data_source, &first).unwrap();
+        assert!(import.is_none());
+        assert_eq!(second.etag, Some("\"1\"".to_string()));
+        assert_eq!(second.hash, first.hash);
+    }
+
+    /// Serves a dataset split into several files, given by name and contents
+    #[derive(Debug)]
+    struct Parts(Vec<(&'static str, &'static str)>);
+
+    impl DataSource for Parts {
+        fn open(&self, _validators: &Validators) -> Result<Fetched<Vec<Body>>, Error> {
+            let bodies = self
+                .0
+                .iter()
+                .map(|(name, data)| Body {
+                    name: name.to_string(),
+                    rdr: Box::new(data.as_bytes()),
+                    compression: None,
+                })
+                .collect();
+            Ok(Fetched::Modified(bodies, Validators::default()))
+        }
+    }
+
+    #[test]
+    fn merges_files_in_order() {
+        let source = source(json!({ "uri": "glob:*.csv", "mode": "dynamic", "key": ["id"] }));
+        let parts = Parts(vec![
+            ("a.csv", "id,name\n1,a\n,b\n2,c\n"),
+            ("b.csv", "id,name\n3,d\n1,e\n,f\n"),
+        ]);
+        let (import, _) = load(&source, &parts, &Validators::default()).unwrap();
+        let import = import.unwrap();
+        assert_eq!(import.map.len(), 3);
+        // by default the row read last wins, also across files
+        assert_eq!(import.map[&vec!["1".to_string()]]["name"], json!("e"));
+
+        let report = &import.report;
+        assert_eq!((report.rows, report.accepted), (6, 3));
+        let files: Vec<(&str, usize, usize, usize, usize)> = report
+            .files
+            .iter()
+            .map(|f| {
+                (
+                    f.file.as_str(),
+                    f.rows,
+                    f.accepted,
+                    f.rejected,
+                    f.duplicates,
+                )
+            })
+            .collect();
+        assert_eq!(files, vec![("a.csv", 3, 2, 1, 0), ("b.csv", 3, 1, 1, 1)]);
+
+        let rejected: Vec<(Option<&str>, u64)> = report
+            .rejected
+            .iter()
+            .map(|r| (r.file.as_ref().map(String::as_str), r.line))
+            .collect();
+        assert_eq!(rejected, vec![(Some("a.csv"), 3), (Some("b.csv"), 4)]);
+        let duplicate = &report.duplicates[0];
+        assert_eq!(duplicate.file.as_ref().map(String::as_str), Some("b.csv"));
+        assert_eq!(duplicate.line, 3);
+    }
+
+    #[test]
+    fn refuses_files_with_another_header() {
+        let source = source(json!({ "uri": "glob:*.csv", "mode": "dynamic", "key": ["id"] }));
+        let parts = Parts(vec![
+            ("a.csv", "id,name\n1,a\n"),
+            ("b.csv", "id,title\n2,b\n"),
+        ]);
+        match load(&source, &parts, &Validators::default()) {
+            Err(Error::Other(message)) => assert!(message.contains("b.csv"), "{}", message),
+            other => panic!("expected the header mismatch, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn keeps_the_kind_of_io_errors() {
+        let source = source(json!({ "uri": "test.csv", "mode": "dynamic" }));
+        let mut import = Import::new(&source);
+        let e = parse_csv(&source, TimingOut(false), &mut import).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn counts_only_new_keys_as_accepted() {
+        let data = "id,name\n1,a\n2,b\n1,c\n";
+        for policy in &["first", "last", "merge", "reject"] {
+            let source = source(json!({
+                "uri": "test.csv",
+                "mode": "dynamic",
+                "key": ["id"],
+                "duplicates": policy,
+            }));
+            let mut import = Import::new(&source);
+            parse_csv(&source, data.as_bytes(), &mut import).unwrap();
+            assert_eq!(import.map.len(), 2);
+            assert_eq!(import.report.rows, 3);
+            assert_eq!(import.report.accepted, 2);
+            assert_eq!(import.report.duplicates.len(), 1);
+        }
+    }
 }
@@ -0,0 +1,256 @@
+//! Module holding the csv dialect of a dataset, i.e. how its csv is to be read
+//!
+//! The `delimiter` is configured on the dataset itself. Setting it to `auto` sniffs it from the
+//! first lines: the candidate splitting them into the same number of fields most consistently
+//! wins, `,` if none does.
+use std::io::{self, Read};
+
+use serde::Deserialize;
+
+/// Value of `delimiter` that has it sniffed from the data
+pub const AUTO: &str = "auto";
+
+/// Number of bytes looked at to sniff the delimiter
+pub const SNIFF: u64 = 16 * 1024;
+
+/// Number of lines looked at to sniff the delimiter
+const LINES: usize = 20;
+
+/// Candidates for `delimiter: auto`, in order of preference on a tie
+const CANDIDATES: &[u8] = b",;\t|";
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Dialect {
+    /// quote character, `"` by default
+    #[serde(default = "default_quote")]
+    pub quote: String,
+    /// escape character for quotes in quoted fields, used instead of doubling them
+    pub escape: Option<String>,
+    /// whether two quotes in a quoted field are read as one
+    #[serde(default = "default_true")]
+    pub double_quote: bool,
+    /// lines starting with this character are skipped
+    pub comment: Option<String>,
+    /// whether the first row is a header row
+    #[serde(default = "default_true")]
+    pub has_headers: bool,
+    /// column names replacing the ones of the header row, required if there is none
+    #[serde(default)]
+    pub columns: Vec<String>,
+    /// whether rows may have more or fewer fields than the header
+    #[serde(default)]
+    pub flexible: bool,
+    #[serde(default)]
+    pub trim: Trim,
+}
+
+/// Whitespace trimmed from the fields
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Trim {
+    None,
+    Headers,
+    Fields,
+    All,
+}
+
+impl Default for Trim {
+    fn default() -> Self {
+        Trim::None
+    }
+}
+
+impl From<Trim> for csv::Trim {
+    fn from(trim: Trim) -> Self {
+        match trim {
+            Trim::None => csv::Trim::None,
+            Trim::Headers => csv::Trim::Headers,
+            Trim::Fields => csv::Trim::Fields,
+            Trim::All => csv::Trim::All,
+        }
+    }
+}
+
+fn default_quote() -> String {
+    "\"".into()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for Dialect {
+    fn default() -> Self {
+        Dialect {
+            quote: default_quote(),
+            escape: None,
+            double_quote: true,
+            comment: None,
+            has_headers: true,
+            columns: Vec::new(),
+            flexible: false,
+            trim: Trim::default(),
+        }
+    }
+}
+
+impl Dialect {
+    /// Check the dialect along with the dataset's `delimiter`
+    pub fn validate(&self, delimiter: &str) -> Result<(), String> {
+        if delimiter != AUTO {
+            byte("delimiter", delimiter)?;
+        }
+        byte("quote", &self.quote)?;
+        if let Some(escape) = &self.escape {
+            byte("escape", escape)?;
+        }
+        if let Some(comment) = &self.comment {
+            byte("comment", comment)?;
+        }
+        if !self.has_headers && self.columns.is_empty() {
+            return Err("columns need to be given if there is no header row".into());
+        }
+        Ok(())
+    }
+
+    /// A csv reader builder for this dialect
+    pub fn builder(&self, delimiter: u8) -> csv::ReaderBuilder {
+        // the dialect was validated along with the settings
+        let first = |value: &String| value.as_bytes()[0];
+
+        let mut builder = csv::ReaderBuilder::new();
+        builder
+            .delimiter(delimiter)
+            .quote(first(&self.quote))
+            .escape(self.escape.as_ref().map(first))
+            .double_quote(self.double_quote)
+            .comment(self.comment.as_ref().map(first))
+            .has_headers(self.has_headers)
+            .flexible(self.flexible)
+            .trim(self.trim.into());
+        builder
+    }
+
+    /// The configured `delimiter`, or the one sniffed from the start of `data` if it is `auto`.
+    /// Whatever was read to sniff it is kept in `head`, to be read again before `data`.
+    pub fn delimiter<R: Read>(
+        &self,
+        delimiter: &str,
+        data: &mut R,
+        head: &mut Vec<u8>,
+    ) -> io::Result<u8> {
+        if delimiter != AUTO {
+            return Ok(delimiter.as_bytes()[0]);
+        }
+        data.by_ref().take(SNIFF).read_to_end(head)?;
+        Ok(sniff(head, self.quote.as_bytes()[0]))
+    }
+}
+
+/// The single ASCII character a setting consists of
+fn byte(name: &str, value: &str) -> Result<u8, String> {
+    match value.as_bytes() {
+        [byte] => Ok(*byte),
+        _ => Err(format!("{} needs to be a single ASCII character", name)),
+    }
+}
+
+/// Sniff the delimiter from the first complete lines of `head`
+fn sniff(head: &[u8], quote: u8) -> u8 {
+    // a full `head` most likely ends in the middle of a line
+    let complete = match head.iter().rposition(|&b| b == b'\n') {
+        Some(end) if head.len() as u64 == SNIFF => &head[..end],
+        _ => head,
+    };
+    let lines: Vec<&[u8]> = complete
+        .split(|&b| b == b'\n')
+        .filter(|line| !line.iter().all(u8::is_ascii_whitespace))
+        .take(LINES)
+        .collect();
+
+    // `max_by_key` returns the last maximum, so the candidates are tried in reverse
+    CANDIDATES
+        .iter()
+        .rev()
+        .copied()
+        .max_by_key(|&delimiter| score(&lines, delimiter, quote))
+        .filter(|&delimiter| score(&lines, delimiter, quote).1 > 0)
+        .unwrap_or(b',')
+}
+
+/// Number of lines with as many fields as the first one, and the number of delimiters in it
+fn score(lines: &[&[u8]], delimiter: u8, quote: u8) -> (usize, usize) {
+    let counts: Vec<usize> = lines
+        .iter()
+        .map(|line| count(line, delimiter, quote))
+        .collect();
+    match counts.first() {
+        Some(&first) if first > 0 => (counts.iter().filter(|&&n| n == first).count(), first),
+        _ => (0, 0),
+    }
+}
+
+/// Number of delimiters in `line` outside of quoted fields
+fn count(line: &[u8], delimiter: u8, quote: u8) -> usize {
+    let mut quoted = false;
+    let mut n = 0;
+    for &b in line {
+        if b == quote {
+            quoted = !quoted;
+        } else if b == delimiter && !quoted {
+            n += 1;
+        }
+    }
+    n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_delimiters_outside_of_quotes() {
+        assert_eq!(count(b"a,b,c", b',', b'"'), 2);
+        assert_eq!(count(b"\"a,b\",c", b',', b'"'), 1);
+        assert_eq!(count(b"'a;b';c;d", b';', b'\''), 2);
+        assert_eq!(count(b"abc", b',', b'"'), 0);
+    }
+
+    #[test]
+    fn scores_consistent_field_counts() {
+        let lines: Vec<&[u8]> = vec![b"a;b;c", b"1;2;3", b"4,5;6"];
+        assert_eq!(score(&lines, b';', b'"'), (2, 2));
+        assert_eq!(score(&lines, b',', b'"'), (0, 0));
+        assert_eq!(score(&[], b',', b'"'), (0, 0));
+    }
+
+    #[test]
+    fn sniffs_the_delimiter() {
+        assert_eq!(sniff(b"id;name;price\n1;foo;1,5\n2;bar;2,5\n", b'"'), b';');
+        assert_eq!(sniff(b"id\tname\n1\tfoo, bar\n", b'"'), b'\t');
+        assert_eq!(sniff(b"id|name\n\"1|2\"|foo\n", b'"'), b'|');
+        assert_eq!(sniff(b"id,name\r\n1,foo\r\n\r\n", b'"'), b',');
+    }
+
+    #[test]
+    fn prefers_the_earlier_candidate_on_a_tie() {
+        assert_eq!(sniff(b"a,b;c\n1,2;3\n", b'"'), b',');
+        assert_eq!(sniff(b"a;b|c\n1;2|3\n", b'"'), b';');
+    }
+
+    #[test]
+    fn falls_back_to_a_comma() {
+        assert_eq!(sniff(b"id\n1\n2\n", b'"'), b',');
+        assert_eq!(sniff(b"", b'"'), b',');
+    }
+
+    #[test]
+    fn reads_the_sniffed_data_again() {
+        let dialect = Dialect::default();
+        let mut data: &[u8] = b"a;b\n1;2\n";
+        let mut head = Vec::new();
+        assert_eq!(dialect.delimiter(AUTO, &mut data, &mut head).unwrap(), b';');
+        assert_eq!(head, b"a;b\n1;2\n");
+        assert_eq!(dialect.delimiter("|", &mut data, &mut head).unwrap(), b'|');
+    }
+}
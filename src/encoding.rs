@@ -0,0 +1,94 @@
+//! Module for transcoding text sources to UTF-8 before they are parsed
+//!
+//! The `encoding` of a dataset is a WHATWG label like `windows-1252` or `latin1` (both meaning
+//! Windows-1252, a superset of Latin-1), or `auto`. A byte order mark always takes precedence and
+//! is stripped. Without an `encoding` the data is expected to be UTF-8 and passed through as is.
+//!
+//! `auto` takes the data for UTF-8 if its first 64 KiB are valid UTF-8, and for Windows-1252
+//! otherwise, which is what most spreadsheet applications export on Windows.
+use std::io::{self, Cursor, Read};
+
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+use encoding_rs_io::DecodeReaderBytesBuilder;
+
+/// Number of bytes looked at to detect the encoding
+const SNIFF: u64 = 64 * 1024;
+
+pub const AUTO: &str = "auto";
+
+/// Check that `label` names an encoding we know
+pub fn validate(label: &str) -> Result<(), String> {
+    if label.eq_ignore_ascii_case(AUTO) || Encoding::for_label(label.as_bytes()).is_some() {
+        Ok(())
+    } else {
+        Err(format!("unknown encoding {}", label))
+    }
+}
+
+/// Wrap `rdr` in a decoder transcoding from the given `encoding` to UTF-8. Sequences that are
+/// invalid in the encoding are replaced with U+FFFD.
+pub fn decode<'a, R: Read + 'a>(
+    mut rdr: R,
+    encoding: Option<&str>,
+) -> io::Result<Box<dyn Read + 'a>> {
+    let label = match encoding {
+        Some(label) => label,
+        None => return Ok(Box::new(builder(None).build(rdr))),
+    };
+
+    if label.eq_ignore_ascii_case(AUTO) {
+        let mut head = Vec::new();
+        rdr.by_ref().take(SNIFF).read_to_end(&mut head)?;
+        let encoding = detect(&head);
+        let rdr = Cursor::new(head).chain(rdr);
+        return Ok(Box::new(builder(Some(encoding)).build(rdr)));
+    }
+
+    let encoding = Encoding::for_label(label.as_bytes()).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Unknown encoding {}", label),
+        )
+    })?;
+    Ok(Box::new(builder(Some(encoding)).build(rdr)))
+}
+
+fn builder(encoding: Option<&'static Encoding>) -> DecodeReaderBytesBuilder {
+    let mut builder = DecodeReaderBytesBuilder::new();
+    builder.encoding(encoding).bom_sniffing(true).strip_bom(true);
+    builder
+}
+
+/// Guess the encoding from the first bytes of the data
+fn detect(head: &[u8]) -> &'static Encoding {
+    match std::str::from_utf8(head) {
+        Ok(_) => UTF_8,
+        // the sniffed bytes may well end in the middle of a character
+        Err(e) if e.error_len().is_none() => UTF_8,
+        Err(_) => WINDOWS_1252,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_utf8() {
+        assert_eq!(detect(b"id,name\n1,foo\n"), UTF_8);
+        assert_eq!(detect("id,name\n1,Müller\n".as_bytes()), UTF_8);
+        assert_eq!(detect(b""), UTF_8);
+    }
+
+    #[test]
+    fn detects_utf8_cut_in_the_middle_of_a_character() {
+        let head = "1,Müller".as_bytes();
+        assert_eq!(detect(&head[..head.len() - 5]), UTF_8);
+    }
+
+    #[test]
+    fn detects_windows_1252() {
+        assert_eq!(detect(b"1,M\xfcller\n"), WINDOWS_1252);
+        assert_eq!(detect(b"1,M\xfcller"), WINDOWS_1252);
+    }
+}
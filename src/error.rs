@@ -7,7 +7,9 @@
 use actix_web::{error::ResponseError, HttpResponse};
 use config;
 use derive_more::Display;
+use notify;
 use reqwest;
+use serde_cbor;
 use std::{io, result};
 
 pub type Result<T> = result::Result<T, Error>;
@@ -17,17 +19,38 @@ pub enum Error {
     #[display(fmt = "BadRequest: {}", _0)]
     BadRequest(String),
 
+    #[display(fmt = "Error decoding body: {}", _0)]
+    Body(io::Error),
+
+    #[display(fmt = "Cbor Error: {}", _0)]
+    Cbor(serde_cbor::Error),
+
+    #[display(fmt = "HTTP Status: {}", _0)]
+    HttpStatus(reqwest::StatusCode),
+
     #[display(fmt = "Internal Server Error")]
     InternalServerError,
 
     Io(io::Error),
 
+    #[display(fmt = "Notify Error: {}", _0)]
+    Notify(notify::Error),
+
     #[display(fmt = "Error: {}", _0)]
     Other(String),
 
+    #[display(fmt = "Reload refused: {}", _0)]
+    Refused(String),
+
     #[display(fmt = "Reqwest Error: {}", _0)]
     Reqwest(reqwest::Error),
 
+    #[display(fmt = "S3 Error: {}", _0)]
+    S3(String),
+
+    #[display(fmt = "Timeout: {}", _0)]
+    Timeout(String),
+
     #[display(fmt = "Unauthorized")]
     Unauthorized,
 
@@ -42,11 +65,18 @@ impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match *self {
             Error::BadRequest(ref _str) => None,
+            Error::Body(ref e) => Some(e),
+            Error::Cbor(ref e) => Some(e),
             Error::ConfigError(ref e) => Some(e),
+            Error::HttpStatus(ref _status) => None,
             Error::InternalServerError => None,
             Error::Io(ref e) => Some(e),
+            Error::Notify(ref e) => Some(e),
             Error::Other(ref _str) => None,
+            Error::Refused(ref _str) => None,
             Error::Reqwest(ref e) => Some(e),
+            Error::S3(ref _str) => None,
+            Error::Timeout(ref _str) => None,
             Error::Unauthorized => None,
             Error::VarError(ref e) => Some(e),
         }
@@ -55,11 +85,18 @@ impl std::error::Error for Error {
     fn description(&self) -> &str {
         match *self {
             Error::BadRequest(ref e) => &e,
+            Error::Body(ref e) => e.description(),
+            Error::Cbor(ref e) => e.description(),
             Error::ConfigError(ref e) => e.description(),
+            Error::HttpStatus(ref status) => status.canonical_reason().unwrap_or("HttpStatus"),
             Error::InternalServerError => "InternalServerError",
             Error::Io(ref e) => e.description(),
+            Error::Notify(ref e) => e.description(),
             Error::Other(ref e) => &e,
+            Error::Refused(ref e) => &e,
             Error::Reqwest(ref e) => e.description(),
+            Error::S3(ref e) => &e,
+            Error::Timeout(ref e) => &e,
             Error::Unauthorized => "Unauthorized",
             Error::VarError(ref e) => e.description(),
         }
@@ -104,6 +141,20 @@ impl From<std::io::Error> for Error {
     }
 }
 
+// From `notify::Error` to an `Error`
+impl From<notify::Error> for Error {
+    fn from(e: notify::Error) -> Error {
+        Error::Notify(e)
+    }
+}
+
+// From `serde_cbor::Error` to an `Error`
+impl From<serde_cbor::Error> for Error {
+    fn from(e: serde_cbor::Error) -> Error {
+        Error::Cbor(e)
+    }
+}
+
 // From `reqwest::Error` to an `Error`
 impl From<reqwest::Error> for Error {
     fn from(e: reqwest::Error) -> Error {
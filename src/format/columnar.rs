@@ -0,0 +1,312 @@
+//! Parquet and Arrow IPC parsers
+//!
+//! Both are read as Arrow record batches, whose columns are mapped onto JSON types: integers,
+//! floats and decimals to numbers, booleans to booleans, strings to strings, dates and timestamps
+//! to ISO 8601 strings. Columns of other types, like nested lists or structs, are skipped with a
+//! warning.
+//!
+//! Only the columns a dataset needs are read: the fields of `Product` in `Mode::Product` and, if
+//! a `schema` is given in `Mode::Dynamic`, the columns it declares. The key columns and the
+//! columns looked up by `joins` are read in either case. Without a schema every column is read.
+//!
+//! Both formats need random access, so the whole file is read into memory before it is parsed.
+use std::io::{self, Cursor, Read};
+use std::sync::Arc;
+
+use arrow::array::{
+    ArrayRef, BooleanArray, Date32Array, Date64Array, DecimalArray, Float32Array, Float64Array,
+    Int16Array, Int32Array, Int64Array, Int8Array, LargeStringArray, StringArray,
+    TimestampMicrosecondArray, TimestampMillisecondArray, TimestampNanosecondArray,
+    TimestampSecondArray, UInt16Array, UInt32Array, UInt64Array, UInt8Array,
+};
+use arrow::datatypes::{DataType, TimeUnit};
+use arrow::ipc::reader::FileReader as IpcReader;
+use arrow::record_batch::RecordBatch;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use parquet::arrow::{ArrowReader, ParquetFileArrowReader};
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::file::serialized_reader::SliceableCursor;
+use serde_json::{Number, Value};
+
+use super::Rows;
+use crate::data::Import;
+use crate::model::{Product, Record};
+use crate::settings::{Csv, Mode};
+
+/// Number of rows decoded at a time
+const BATCH_SIZE: usize = 8192;
+
+pub fn parse_parquet<R: Read>(source: &Csv, mut data: R, import: &mut Import) -> io::Result<()> {
+    let mut buf = Vec::new();
+    data.read_to_end(&mut buf)?;
+    let reader = SerializedFileReader::new(SliceableCursor::new(buf)).map_err(invalid)?;
+
+    // the columns are selected by their leaves, a nested column consists of several of them
+    let descr = reader.metadata().file_metadata().schema_descr_ptr();
+    let mut reader = ParquetFileArrowReader::new(Arc::new(reader));
+    let schema = reader.get_schema().map_err(invalid)?;
+    let names: Vec<String> = schema.fields().iter().map(|f| f.name().clone()).collect();
+    check(source, &names)?;
+
+    let wanted = wanted(source);
+    let mut skipped: Vec<&str> = Vec::new();
+    let mut indices = Vec::new();
+    let mut headers = Vec::new();
+    for i in 0..descr.num_columns() {
+        let root = descr.get_column_root(i);
+        let name = root.name();
+        if !is_wanted(&wanted, name) || skipped.contains(&name) {
+            continue;
+        }
+        let data_type = schema.field_with_name(name).map_err(invalid)?.data_type();
+        if root.is_primitive() && is_supported(data_type) {
+            indices.push(i);
+            headers.push(name.to_string());
+        } else {
+            skip(source, name, data_type)?;
+            skipped.push(name);
+        }
+    }
+
+    let batches = reader
+        .get_record_reader_by_columns(indices, BATCH_SIZE)
+        .map_err(invalid)?;
+    let batches = batches.map(|batch| batch.map_err(invalid));
+    parse_batches(source, batches, &wanted, headers, import)
+}
+
+pub fn parse_arrow<R: Read>(source: &Csv, mut data: R, import: &mut Import) -> io::Result<()> {
+    let mut buf = Vec::new();
+    data.read_to_end(&mut buf)?;
+    let reader = IpcReader::try_new(Cursor::new(buf)).map_err(invalid)?;
+
+    let schema = reader.schema();
+    let names: Vec<String> = schema.fields().iter().map(|f| f.name().clone()).collect();
+    check(source, &names)?;
+    let wanted = wanted(source);
+    let mut headers = Vec::new();
+    for field in schema.fields() {
+        if !is_wanted(&wanted, field.name()) {
+            continue;
+        }
+        if is_supported(field.data_type()) {
+            headers.push(field.name().clone());
+        } else {
+            skip(source, field.name(), field.data_type())?;
+        }
+    }
+
+    let batches = reader.map(|batch| batch.map_err(invalid));
+    parse_batches(source, batches, &wanted, headers, import)
+}
+
+/// The columns to read, `None` meaning all of them
+fn wanted(source: &Csv) -> Option<Vec<&str>> {
+    let mut columns = match (source.mode, &source.schema) {
+        (Mode::Product, _) => Product::COLUMNS.to_vec(),
+        (Mode::Dynamic, Some(schema)) => schema.keys().map(String::as_str).collect(),
+        (Mode::Dynamic, None) => return None,
+    };
+    columns.extend(source.key.columns());
+    for join in &source.joins {
+        columns.extend(join.columns(source));
+    }
+    Some(columns)
+}
+
+fn is_wanted(wanted: &Option<Vec<&str>>, name: &str) -> bool {
+    wanted
+        .as_ref()
+        .map_or(true, |wanted| wanted.contains(&name))
+}
+
+/// Fail early if a key column is missing, rather than rejecting every single row
+fn check(source: &Csv, names: &[String]) -> io::Result<()> {
+    match source
+        .key
+        .columns()
+        .into_iter()
+        .find(|column| !names.iter().any(|name| name.as_str() == *column))
+    {
+        Some(column) => Err(invalid(format!(
+            "Key column {} not found in {}",
+            column, source.uri
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Whether `to_value` can map values of the type
+fn is_supported(data_type: &DataType) -> bool {
+    match data_type {
+        DataType::Boolean
+        | DataType::Int8
+        | DataType::Int16
+        | DataType::Int32
+        | DataType::Int64
+        | DataType::UInt8
+        | DataType::UInt16
+        | DataType::UInt32
+        | DataType::UInt64
+        | DataType::Float32
+        | DataType::Float64
+        | DataType::Decimal(_, _)
+        | DataType::Utf8
+        | DataType::LargeUtf8
+        | DataType::Date32(_)
+        | DataType::Date64(_)
+        | DataType::Timestamp(_, _) => true,
+        _ => false,
+    }
+}
+
+/// Leave out a column of an unsupported type. A key column can't be left out, that fails the
+/// whole load rather than rejecting every single row.
+fn skip(source: &Csv, name: &str, data_type: &DataType) -> io::Result<()> {
+    if source.key.columns().contains(&name) {
+        return Err(invalid(format!(
+            "Key column {} of {} has the unsupported type {:?}",
+            name, source.uri, data_type
+        )));
+    }
+    eprintln!(
+        "Skipping column {} of {}, its type {:?} is not supported",
+        name, source.uri, data_type
+    );
+    Ok(())
+}
+
+fn parse_batches<I>(
+    source: &Csv,
+    batches: I,
+    wanted: &Option<Vec<&str>>,
+    headers: Vec<String>,
+    import: &mut Import,
+) -> io::Result<()>
+where
+    I: Iterator<Item = io::Result<RecordBatch>>,
+{
+    let mut rows = Rows::with_headers(source, import, headers);
+    let mut line = 0;
+
+    for batch in batches {
+        let batch = batch?;
+        let schema = batch.schema();
+        let columns: Vec<(&String, &ArrayRef)> = schema
+            .fields()
+            .iter()
+            .map(|field| field.name())
+            .zip(batch.columns())
+            .filter(|(name, column)| is_wanted(wanted, name) && is_supported(column.data_type()))
+            .collect();
+
+        'rows: for i in 0..batch.num_rows() {
+            // there are no lines, rows are numbered by their position in the file, starting at 1
+            line += 1;
+            let mut record = Record::new();
+            for (name, column) in &columns {
+                match to_value(column, i) {
+                    Ok(value) => {
+                        record.insert((*name).clone(), value);
+                    }
+                    Err(reason) => {
+                        let report = &mut rows.import.report;
+                        report.rows += 1;
+                        report.reject(line, Some(name.as_str()), None, reason);
+                        continue 'rows;
+                    }
+                }
+            }
+            rows.add(line, Value::Object(record))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Map the `i`th value of a column to the matching JSON value
+fn to_value(column: &ArrayRef, i: usize) -> Result<Value, String> {
+    if column.is_null(i) {
+        return Ok(Value::Null);
+    }
+
+    macro_rules! value {
+        ($array:ty) => {
+            column.as_any().downcast_ref::<$array>().unwrap().value(i)
+        };
+    }
+
+    let value = match column.data_type() {
+        DataType::Boolean => Value::Bool(value!(BooleanArray)),
+        DataType::Int8 => Value::from(value!(Int8Array)),
+        DataType::Int16 => Value::from(value!(Int16Array)),
+        DataType::Int32 => Value::from(value!(Int32Array)),
+        DataType::Int64 => Value::from(value!(Int64Array)),
+        DataType::UInt8 => Value::from(value!(UInt8Array)),
+        DataType::UInt16 => Value::from(value!(UInt16Array)),
+        DataType::UInt32 => Value::from(value!(UInt32Array)),
+        DataType::UInt64 => Value::from(value!(UInt64Array)),
+        DataType::Float32 => float(f64::from(value!(Float32Array))),
+        DataType::Float64 => float(value!(Float64Array)),
+        DataType::Decimal(_, scale) => {
+            float(value!(DecimalArray) as f64 / 10f64.powi(*scale as i32))
+        }
+        DataType::Utf8 => Value::String(value!(StringArray).into()),
+        DataType::LargeUtf8 => Value::String(value!(LargeStringArray).into()),
+        DataType::Date32(_) => {
+            let days = value!(Date32Array);
+            let date = NaiveDate::from_ymd(1970, 1, 1)
+                .checked_add_signed(chrono::Duration::days(i64::from(days)))
+                .ok_or_else(|| format!("is out of range: {} days since 1970-01-01", days))?;
+            Value::String(date.format("%Y-%m-%d").to_string())
+        }
+        DataType::Date64(_) => timestamp(value!(Date64Array), 1_000)?,
+        DataType::Timestamp(unit, _) => match unit {
+            TimeUnit::Second => timestamp(value!(TimestampSecondArray), 1)?,
+            TimeUnit::Millisecond => timestamp(value!(TimestampMillisecondArray), 1_000)?,
+            TimeUnit::Microsecond => timestamp(value!(TimestampMicrosecondArray), 1_000_000)?,
+            TimeUnit::Nanosecond => timestamp(value!(TimestampNanosecondArray), 1_000_000_000)?,
+        },
+        other => return Err(format!("has the unsupported type {:?}", other)),
+    };
+    Ok(value)
+}
+
+/// JSON has no NaN or infinity, those become `null`
+fn float(value: f64) -> Value {
+    Number::from_f64(value).map_or(Value::Null, Value::Number)
+}
+
+/// Format a timestamp given in `1 / per_second` seconds since the epoch as RFC 3339
+fn timestamp(value: i64, per_second: i64) -> Result<Value, String> {
+    let secs = value.div_euclid(per_second);
+    let nanos = value.rem_euclid(per_second) * (1_000_000_000 / per_second);
+    let datetime = NaiveDateTime::from_timestamp_opt(secs, nanos as u32)
+        .ok_or_else(|| format!("is out of range: {} seconds since the epoch", secs))?;
+    Ok(Value::String(
+        DateTime::<Utc>::from_utc(datetime, Utc).to_rfc3339(),
+    ))
+}
+
+fn invalid<E: ToString>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_timestamps() {
+        assert_eq!(
+            timestamp(1_500, 1_000),
+            Ok(Value::String("1970-01-01T00:00:01.500+00:00".into()))
+        );
+        assert_eq!(
+            timestamp(-1, 1),
+            Ok(Value::String("1969-12-31T23:59:59+00:00".into()))
+        );
+        assert!(timestamp(i64::MAX, 1).is_err());
+        assert!(timestamp(i64::MIN, 1_000).is_err());
+    }
+}
@@ -0,0 +1,99 @@
+//! JSON Lines and JSON array parsers
+//!
+//! Every object becomes a row, its fields the columns.
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read};
+
+use serde::de::{self, Deserializer as _, SeqAccess, Visitor};
+use serde_json;
+
+use super::Rows;
+use crate::data::Import;
+use crate::settings::Csv;
+
+/// Parse one JSON object per line. Empty lines are skipped, lines that are not valid JSON are
+/// rejected like any other bogus row.
+pub fn parse_jsonl<R: Read>(source: &Csv, data: R, import: &mut Import) -> io::Result<()> {
+    let mut rows = Rows::new(source, import);
+    let mut rdr = BufReader::new(data);
+    let mut buf = Vec::new();
+    let mut line = 0;
+
+    loop {
+        buf.clear();
+        if rdr.read_until(b'\n', &mut buf)? == 0 {
+            break;
+        }
+        line += 1;
+        if buf.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+        match serde_json::from_slice(&buf) {
+            Ok(value) => rows.add(line, value)?,
+            Err(e) => {
+                rows.import.report.rows += 1;
+                rows.import.report.reject(line, None, None, e.to_string());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Parse a single JSON array of objects. The array is streamed, so only one object is held in
+/// memory at a time. As there are no lines, rejected rows are numbered by their position in the
+/// array, starting at 1.
+pub fn parse_json<R: Read>(source: &Csv, data: R, import: &mut Import) -> io::Result<()> {
+    let mut rows = Rows::new(source, import);
+    let mut de = serde_json::Deserializer::from_reader(BufReader::new(data));
+    (&mut de).deserialize_seq(Elements(&mut rows))?;
+    de.end()?;
+    Ok(())
+}
+
+/// Visits the elements of the top level array one by one
+struct Elements<'a, 'b>(&'a mut Rows<'b>);
+
+impl<'de, 'a, 'b> Visitor<'de> for Elements<'a, 'b> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an array of objects")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        let mut index = 0;
+        while let Some(value) = seq.next_element()? {
+            index += 1;
+            self.0.add(index, value).map_err(de::Error::custom)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn collects_the_fields_of_all_objects_as_headers() {
+        let source: Csv = serde_json::from_value(
+            json!({ "uri": "test.jsonl", "mode": "dynamic", "key": ["id"] }),
+        )
+        .unwrap();
+        let data = r#"{"id": 1, "name": "a"}
+{"id": 2, "price": 3}
+{"name": "c", "id": 3}
+"#;
+        let mut import = Import::new(&source);
+        parse_jsonl(&source, data.as_bytes(), &mut import).unwrap();
+        assert_eq!(import.report.headers, vec!["id", "name", "price"]);
+        assert_eq!(import.report.accepted, 3);
+
+        // a second file adds to the headers of the first one
+        let data = r#"{"id": 4, "color": "red"}"#;
+        parse_jsonl(&source, data.as_bytes(), &mut import).unwrap();
+        assert_eq!(import.report.headers, vec!["id", "name", "price", "color"]);
+    }
+}
@@ -0,0 +1,209 @@
+//! Module holding the parsers for the formats a dataset can be provided in
+//!
+//! Every parser turns the raw (decompressed) bytes into the same `Import`, i.e. records keyed by
+//! the dataset's `key` columns and a report of the rejected rows, so everything downstream does
+//! not care about the format. The rows are added to an existing `Import`, so a dataset split into
+//! several files is merged while it is parsed.
+//!
+//! Unlike csv cells, the values of the other formats may be typed already. Values of
+//! columns the `schema` declares are converted like csv cells if they are strings, missing or
+//! `null`, typed ones are kept as they are.
+use std::collections::HashSet;
+use std::io::{self, Read};
+
+use serde::Deserialize;
+use serde_json::{self, Value};
+
+use crate::data::{insert, parse_csv, Import};
+use crate::model::{Key, Product, Record};
+use crate::schema::{ConversionError, Schema};
+use crate::settings::{Csv, Mode};
+
+#[cfg(feature = "columnar")]
+mod columnar;
+mod json;
+#[cfg(feature = "spreadsheet")]
+mod sheet;
+
+#[cfg(feature = "columnar")]
+pub use self::columnar::{parse_arrow, parse_parquet};
+pub use self::json::{parse_json, parse_jsonl};
+#[cfg(feature = "spreadsheet")]
+pub use self::sheet::{parse_ods, parse_xlsx};
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Csv,
+    /// one JSON object per line, also known as NDJSON
+    #[serde(alias = "ndjson")]
+    Jsonl,
+    /// a single JSON array of objects
+    Json,
+    /// an Excel workbook, requires the `spreadsheet` feature
+    Xlsx,
+    /// an OpenDocument spreadsheet, requires the `spreadsheet` feature
+    Ods,
+    /// requires the `columnar` feature
+    Parquet,
+    /// an Arrow IPC file, requires the `columnar` feature, also known as Feather (version 2)
+    #[serde(alias = "feather")]
+    Arrow,
+}
+
+impl Format {
+    /// Whether the format is text, as opposed to a binary one that is never transcoded
+    pub fn is_text(self) -> bool {
+        match self {
+            Format::Csv | Format::Jsonl | Format::Json => true,
+            Format::Xlsx | Format::Ods | Format::Parquet | Format::Arrow => false,
+        }
+    }
+
+    /// Whether this build supports the format, see the cargo features
+    pub fn is_available(self) -> bool {
+        match self {
+            Format::Csv | Format::Jsonl | Format::Json => true,
+            Format::Xlsx | Format::Ods => cfg!(feature = "spreadsheet"),
+            Format::Parquet | Format::Arrow => cfg!(feature = "columnar"),
+        }
+    }
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Format::Csv
+    }
+}
+
+/// Parse `data` according to the `format` of the dataset, adding its rows to `import`
+pub fn parse<R: Read>(source: &Csv, data: R, import: &mut Import) -> io::Result<()> {
+    match source.format {
+        Format::Csv => parse_csv(source, data, import),
+        Format::Jsonl => parse_jsonl(source, data, import),
+        Format::Json => parse_json(source, data, import),
+        #[cfg(feature = "spreadsheet")]
+        Format::Xlsx => parse_xlsx(source, data, import),
+        #[cfg(feature = "spreadsheet")]
+        Format::Ods => parse_ods(source, data, import),
+        #[cfg(feature = "columnar")]
+        Format::Parquet => parse_parquet(source, data, import),
+        #[cfg(feature = "columnar")]
+        Format::Arrow => parse_arrow(source, data, import),
+        // formats this build lacks are refused along with the settings already
+        #[allow(unreachable_patterns)]
+        format => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{:?} is not supported by this build", format),
+        )),
+    }
+}
+
+/// Adds the rows of a source whose values may be typed already to an `Import`
+struct Rows<'a> {
+    source: &'a Csv,
+    columns: Vec<&'a str>,
+    import: &'a mut Import,
+    /// the fields seen so far, if the headers are discovered from the rows
+    seen: Option<HashSet<String>>,
+}
+
+impl<'a> Rows<'a> {
+    /// Rows whose fields may differ, the headers are all fields seen in order
+    fn new(source: &'a Csv, import: &'a mut Import) -> Self {
+        let seen = import.report.headers.iter().cloned().collect();
+        Rows {
+            source,
+            columns: source.key.columns(),
+            import,
+            seen: Some(seen),
+        }
+    }
+
+    /// Rows of a source whose `headers` are known up front
+    fn with_headers(source: &'a Csv, import: &'a mut Import, headers: Vec<String>) -> Self {
+        import.report.headers = headers;
+        Rows {
+            source,
+            columns: source.key.columns(),
+            import,
+            seen: None,
+        }
+    }
+
+    /// Add a row, failing only if the dataset's `duplicates` policy says so
+    fn add(&mut self, line: u64, value: Value) -> io::Result<()> {
+        let report = &mut self.import.report;
+        report.rows += 1;
+
+        let object = match value {
+            Value::Object(object) => object,
+            _ => {
+                report.reject(line, None, None, "not an object");
+                return Ok(());
+            }
+        };
+        if let Some(seen) = &mut self.seen {
+            for column in object.keys() {
+                if !seen.contains(column) {
+                    seen.insert(column.clone());
+                    report.headers.push(column.clone());
+                }
+            }
+        }
+
+        let mut id = Key::with_capacity(self.columns.len());
+        for column in &self.columns {
+            let value = match object.get(*column) {
+                Some(Value::String(value)) => value.trim().to_string(),
+                None | Some(Value::Null) => String::new(),
+                Some(value) => value.to_string(),
+            };
+            if value.is_empty() {
+                report.reject(line, Some(*column), None, "empty key");
+                return Ok(());
+            }
+            id.push(value);
+        }
+
+        let record = match self.source.mode {
+            Mode::Product => serde_json::from_value::<Product>(Value::Object(object))
+                .map(Product::into_record)
+                .map_err(|e| report.reject(line, None, None, e.to_string())),
+            Mode::Dynamic => to_record(object, self.source.schema.as_ref())
+                .map_err(|e| report.reject(line, Some(&e.column), Some(&e.value), e.reason)),
+        };
+        if let Ok(record) = record {
+            let accepted = insert(self.import, self.source.duplicates, line, id, record)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            if accepted {
+                self.import.report.accepted += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Convert the fields of `object` the `schema` declares, unless they are typed already
+fn to_record(mut object: Record, schema: Option<&Schema>) -> Result<Record, ConversionError> {
+    let schema = match schema {
+        Some(schema) => schema,
+        None => return Ok(object),
+    };
+    for (column, definition) in schema {
+        let raw = match object.get(column) {
+            Some(Value::String(raw)) => raw.clone(),
+            None | Some(Value::Null) => String::new(),
+            Some(_) => continue,
+        };
+        let value = definition
+            .convert(&raw)
+            .map_err(|reason| ConversionError {
+                column: column.clone(),
+                value: raw.clone(),
+                reason,
+            })?;
+        object.insert(column.clone(), value);
+    }
+    Ok(object)
+}
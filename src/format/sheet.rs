@@ -0,0 +1,160 @@
+//! Excel (XLSX) and OpenDocument (ODS) spreadsheet parsers
+//!
+//! A single sheet is read, the first one unless `spreadsheet.sheet` names another. The header is
+//! taken from `spreadsheet.header_row`, every non-empty row below it becomes a row of the dataset.
+//! Cells are mapped to JSON types: numbers to numbers (whole ones to integers), booleans to
+//! booleans, dates to ISO 8601 strings and empty cells to `null`. Rows with error cells, like
+//! `#DIV/0!`, are rejected.
+use std::fmt::Display;
+use std::io::{self, Cursor, Read};
+
+use calamine::{DataType, Ods, Reader, Xlsx};
+use chrono::{Duration, NaiveDate};
+use serde_json::{Number, Value};
+
+use super::Rows;
+use crate::data::Import;
+use crate::model::Record;
+use crate::settings::Csv;
+
+pub fn parse_xlsx<R: Read>(source: &Csv, data: R, import: &mut Import) -> io::Result<()> {
+    parse_workbook::<Xlsx<_>, _>(source, data, import)
+}
+
+pub fn parse_ods<R: Read>(source: &Csv, data: R, import: &mut Import) -> io::Result<()> {
+    parse_workbook::<Ods<_>, _>(source, data, import)
+}
+
+/// Spreadsheets are zip archives, which can't be streamed. The whole file is read into memory
+/// before the sheet is parsed.
+fn parse_workbook<W, R>(source: &Csv, mut data: R, import: &mut Import) -> io::Result<()>
+where
+    W: Reader<RS = Cursor<Vec<u8>>>,
+    W::Error: Display,
+    R: Read,
+{
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+
+    let mut buf = Vec::new();
+    data.read_to_end(&mut buf)?;
+    let mut workbook = W::new(Cursor::new(buf)).map_err(|e| invalid(e.to_string()))?;
+
+    let settings = &source.spreadsheet;
+    let sheet = match &settings.sheet {
+        Some(sheet) => sheet.clone(),
+        None => workbook
+            .sheet_names()
+            .first()
+            .cloned()
+            .ok_or_else(|| invalid(format!("No sheets found in {}", source.uri)))?,
+    };
+    let range = workbook
+        .worksheet_range(&sheet)
+        .ok_or_else(|| invalid(format!("Sheet {} not found in {}", sheet, source.uri)))?
+        .map_err(|e| invalid(e.to_string()))?;
+
+    // the range starts at the first non-empty cell rather than at A1
+    let top = range.start().map_or(0, |(row, _)| row as usize);
+    let empty = || invalid(format!("Header row {} of {} is empty", settings.header_row, sheet));
+    let skip = (settings.header_row - 1).checked_sub(top).ok_or_else(empty)?;
+    let mut lines = range.rows().skip(skip);
+    let headers: Vec<String> = lines
+        .next()
+        .ok_or_else(empty)?
+        .iter()
+        .map(|cell| match cell {
+            DataType::String(header) => header.trim().to_string(),
+            DataType::Empty => String::new(),
+            cell => cell.to_string(),
+        })
+        .collect();
+
+    let named = headers.iter().filter(|h| !h.is_empty()).cloned().collect();
+    let mut rows = Rows::with_headers(source, import, named);
+    // line numbers as shown by the spreadsheet application, starting at 1
+    let first = (top + skip) as u64 + 2;
+
+    'rows: for (i, cells) in lines.enumerate() {
+        if cells.iter().all(|cell| *cell == DataType::Empty) {
+            continue;
+        }
+        let line = first + i as u64;
+
+        let mut record = Record::new();
+        for (header, cell) in headers.iter().zip(cells) {
+            // columns without a header are ignored, like notes next to the table
+            if header.is_empty() {
+                continue;
+            }
+            match to_value(cell) {
+                Ok(value) => {
+                    record.insert(header.clone(), value);
+                }
+                Err(reason) => {
+                    let report = &mut rows.import.report;
+                    report.rows += 1;
+                    let value = cell.to_string();
+                    report.reject(line, Some(header.as_str()), Some(&value), reason);
+                    continue 'rows;
+                }
+            }
+        }
+        rows.add(line, Value::Object(record))?;
+    }
+
+    Ok(())
+}
+
+/// Map a cell to the matching JSON value
+fn to_value(cell: &DataType) -> Result<Value, String> {
+    match cell {
+        DataType::Empty => Ok(Value::Null),
+        DataType::String(value) => Ok(Value::String(value.clone())),
+        DataType::Bool(value) => Ok(Value::Bool(*value)),
+        DataType::Int(value) => Ok(Value::from(*value)),
+        // spreadsheets store every number as a float, ids included
+        DataType::Float(value) if value.fract() == 0.0 && value.abs() < 1e15 => {
+            Ok(Value::from(*value as i64))
+        }
+        DataType::Float(value) => Number::from_f64(*value)
+            .map(Value::Number)
+            .ok_or_else(|| "is not a number".into()),
+        DataType::DateTime(serial) => to_datetime(*serial).map(Value::String),
+        DataType::Error(e) => Err(format!("is an error: {}", e)),
+    }
+}
+
+/// Format a date serial, i.e. the days since 1899-12-30, as a date or, if it has a time of day,
+/// as a datetime
+fn to_datetime(serial: f64) -> Result<String, String> {
+    let out_of_range = || format!("is out of range: {} days since 1899-12-30", serial);
+    let millis = (serial * 86_400_000.0).round();
+    // beyond this `Duration` overflows, far beyond any date chrono can represent anyway
+    if !millis.is_finite() || millis.abs() >= 1e18 {
+        return Err(out_of_range());
+    }
+    let epoch = NaiveDate::from_ymd(1899, 12, 30).and_hms(0, 0, 0);
+    let datetime = epoch
+        .checked_add_signed(Duration::milliseconds(millis as i64))
+        .ok_or_else(out_of_range)?;
+    if serial.fract() == 0.0 {
+        Ok(datetime.format("%Y-%m-%d").to_string())
+    } else {
+        Ok(datetime.format("%Y-%m-%dT%H:%M:%S").to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_date_serials() {
+        assert_eq!(to_datetime(43831.0), Ok("2020-01-01".to_string()));
+        assert_eq!(to_datetime(43831.5), Ok("2020-01-01T12:00:00".to_string()));
+        assert_eq!(to_datetime(0.0), Ok("1899-12-30".to_string()));
+        assert!(to_datetime(1e12).is_err());
+        assert!(to_datetime(-1e300).is_err());
+        assert!(to_datetime(std::f64::NAN).is_err());
+    }
+}
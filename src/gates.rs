@@ -0,0 +1,206 @@
+//! Module holding the safety gates a reload has to pass before it replaces the current data
+//!
+//! A truncated export or a broken upstream would otherwise happily replace a good dataset with
+//! a broken one.
+use serde::Deserialize;
+
+use crate::data::{Dataset, Import};
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Gates {
+    /// maximum share of rejected rows, between 0 and 1
+    pub max_rejected_ratio: Option<f64>,
+    /// maximum drop of the row count compared to the current data, in percent
+    pub max_row_drop: Option<f64>,
+    /// minimum number of rows
+    pub min_rows: Option<usize>,
+    /// whether the header row has to be the same as the one of the current data
+    #[serde(default)]
+    pub check_headers: bool,
+}
+
+impl Gates {
+    /// Check the definition for values that could never be met
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(ratio) = self.max_rejected_ratio {
+            if ratio < 0.0 || ratio > 1.0 {
+                return Err("max_rejected_ratio needs to be between 0 and 1".into());
+            }
+        }
+        if let Some(drop) = self.max_row_drop {
+            if drop < 0.0 || drop > 100.0 {
+                return Err("max_row_drop needs to be between 0 and 100".into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Check whether `import` may replace the `current` data. On startup there is no current data,
+    /// so only the gates looking at the import alone apply, `max_rejected_ratio` and `min_rows`.
+    /// A dataset failing those on startup fails the start.
+    pub fn check(&self, current: Option<&Dataset>, import: &Import) -> Result<(), String> {
+        let report = &import.report;
+
+        if let Some(max) = self.max_rejected_ratio {
+            let ratio = if report.rows == 0 {
+                0.0
+            } else {
+                report.rejected.len() as f64 / report.rows as f64
+            };
+            if ratio > max {
+                return Err(format!(
+                    "{} of {} rows rejected, more than the allowed ratio of {}",
+                    report.rejected.len(),
+                    report.rows,
+                    max
+                ));
+            }
+        }
+
+        if let Some(min) = self.min_rows {
+            if import.map.len() < min {
+                return Err(format!(
+                    "{} rows, less than the required {}",
+                    import.map.len(),
+                    min
+                ));
+            }
+        }
+
+        let current = match current {
+            Some(current) => current,
+            None => return Ok(()),
+        };
+
+        if let Some(max) = self.max_row_drop {
+            let before = current.raw.len();
+            let after = import.map.len();
+            if before > 0 && after < before {
+                let drop = (before - after) as f64 * 100.0 / before as f64;
+                if drop > max {
+                    return Err(format!(
+                        "row count dropped by {:.1}% from {} to {}, more than the allowed {}%",
+                        drop, before, after, max
+                    ));
+                }
+            }
+        }
+
+        if self.check_headers && current.report.headers != report.headers {
+            return Err(format!(
+                "header changed from {:?} to {:?}",
+                current.report.headers, report.headers
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use crate::data::parse_csv;
+    use crate::settings::Csv;
+    use crate::source::StaticSource;
+
+    fn source() -> Csv {
+        serde_json::from_value(json!({ "uri": "test.csv", "mode": "dynamic", "key": ["id"] }))
+            .unwrap()
+    }
+
+    /// A csv with the given header and `rows` rows with a key, followed by `keyless` without one
+    fn csv(header: &str, rows: usize, keyless: usize) -> String {
+        let mut csv = format!("{}\n", header);
+        for i in 0..rows {
+            csv.push_str(&format!("{},a\n", i));
+        }
+        for _ in 0..keyless {
+            csv.push_str(",a\n");
+        }
+        csv
+    }
+
+    fn import(csv: &str) -> Import {
+        let source = source();
+        let mut import = Import::new(&source);
+        parse_csv(&source, csv.as_bytes(), &mut import).unwrap();
+        import
+    }
+
+    fn dataset(csv: String) -> Dataset {
+        Dataset::open_with(source(), Arc::new(StaticSource(csv))).unwrap()
+    }
+
+    #[test]
+    fn limits_the_share_of_rejected_rows() {
+        let gates = |ratio| Gates {
+            max_rejected_ratio: Some(ratio),
+            ..Gates::default()
+        };
+        // an empty import rejects nothing
+        assert!(gates(0.0).check(None, &import("id,name\n")).is_ok());
+
+        let import = import(&csv("id,name", 9, 1));
+        assert_eq!(import.report.rows, 10);
+        assert_eq!(import.report.rejected.len(), 1);
+        assert!(gates(0.1).check(None, &import).is_ok());
+        assert!(gates(0.09).check(None, &import).is_err());
+    }
+
+    #[test]
+    fn limits_the_drop_of_the_row_count() {
+        let current = dataset(csv("id,name", 10, 0));
+        let import = import(&csv("id,name", 8, 0));
+
+        let gates = |drop| Gates {
+            max_row_drop: Some(drop),
+            ..Gates::default()
+        };
+        assert!(gates(20.0).check(Some(&current), &import).is_ok());
+        assert!(gates(19.9).check(Some(&current), &import).is_err());
+        // nothing to compare against on startup
+        assert!(gates(0.0).check(None, &import).is_ok());
+        // growing is fine
+        assert!(gates(0.0)
+            .check(Some(&dataset(csv("id,name", 5, 0))), &import)
+            .is_ok());
+    }
+
+    #[test]
+    fn requires_a_minimum_of_rows() {
+        let import = import(&csv("id,name", 8, 2));
+
+        let gates = |min| Gates {
+            min_rows: Some(min),
+            ..Gates::default()
+        };
+        assert!(gates(8).check(None, &import).is_ok());
+        assert!(gates(9).check(None, &import).is_err());
+        assert!(gates(9)
+            .check(Some(&dataset(csv("id,name", 8, 0))), &import)
+            .is_err());
+    }
+
+    #[test]
+    fn checks_the_header_row_against_the_current_data() {
+        let current = dataset(csv("id,name", 3, 0));
+        let gates = Gates {
+            check_headers: true,
+            ..Gates::default()
+        };
+
+        assert!(gates
+            .check(Some(&current), &import(&csv("id,name", 2, 0)))
+            .is_ok());
+        let renamed = import(&csv("id,title", 3, 0));
+        assert!(gates.check(Some(&current), &renamed).is_err());
+        // nothing to compare against on startup
+        assert!(gates.check(None, &renamed).is_ok());
+        assert!(Gates::default().check(Some(&current), &renamed).is_ok());
+    }
+}
@@ -1,12 +1,18 @@
+use crate::changes::Changeset;
 use crate::data;
 use crate::error::Error as ServiceError;
 use crate::jwt;
+use crate::report::ImportReport;
+use crate::routes::DatasetName;
 use crate::user;
 use actix_identity::Identity;
 use actix_web::{http::StatusCode, web, Error, HttpResponse, Responder};
 use csrf_token::CsrfTokenGenerator;
 use futures::future::{ok, Future};
 use hex;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
 use std::env;
 use std::sync::{Arc, Mutex};
 
@@ -16,21 +22,95 @@ pub fn index() -> impl Responder {
     HttpResponse::Ok().body(format!("Rust {}", rust))
 }
 
-/// Asynchronous product handler
+/// Asynchronous product handler, looking up a row of the dataset served by the current scope by
+/// its (possibly composite) key
 pub fn product(
-    path: web::Path<(usize,)>,
+    path: web::Path<Vec<String>>,
+    dataset: web::Data<DatasetName>,
     data: web::Data<Arc<Mutex<data::AppState>>>,
     auth: user::SlimUser,
 ) -> impl Future<Item = HttpResponse, Error = Error> {
-    let map = &data.lock().unwrap().map;
+    let map = match data.lock().unwrap().datasets.get(&dataset.0) {
+        Some(dataset) => dataset.map.clone(),
+        None => return ok(HttpResponse::new(StatusCode::NOT_FOUND)),
+    };
     dbg!("auth: {:?}", auth);
-    if let Some(product) = map.get(&path.0) {
-        ok(HttpResponse::Ok().json(product))
+    if let Some(record) = map.get(&*path) {
+        ok(HttpResponse::Ok().json(record))
     } else {
         ok(HttpResponse::new(StatusCode::NOT_FOUND))
     }
 }
 
+/// Query of the changes feed
+#[derive(Debug, Deserialize)]
+pub struct ChangesQuery {
+    /// epoch of the generation the client has seen last, the current one if not given
+    pub epoch: Option<u64>,
+    /// generation the client has seen last
+    pub since: u64,
+}
+
+/// Changes of the dataset served by the current scope since the generation the client has seen
+/// last. Responds with `410 Gone` if those aren't known (anymore), e.g. after a restart, the client
+/// has to fetch everything again then and continue from the current epoch and generation.
+pub fn changes(
+    query: web::Query<ChangesQuery>,
+    dataset: web::Data<DatasetName>,
+    data: web::Data<Arc<Mutex<data::AppState>>>,
+    _auth: user::SlimUser,
+) -> HttpResponse {
+    let (epoch, generation, changes) = match data.lock().unwrap().datasets.get(&dataset.0) {
+        Some(dataset) => (
+            dataset.changes.epoch,
+            dataset.changes.generation,
+            dataset
+                .changes
+                .since(query.epoch.unwrap_or(dataset.changes.epoch), query.since),
+        ),
+        None => return HttpResponse::new(StatusCode::NOT_FOUND),
+    };
+    match changes {
+        Some(changes) => {
+            let changes: Vec<&Changeset> = changes.iter().map(|changeset| &**changeset).collect();
+            HttpResponse::Ok().json(json!({
+                "epoch": epoch,
+                "generation": generation,
+                "changes": changes,
+            }))
+        }
+        None => HttpResponse::Gone().json(json!({
+            "epoch": epoch,
+            "generation": generation,
+            "message": format!(
+                "Changes since generation {} of epoch {} are unknown",
+                query.since,
+                query.epoch.unwrap_or(epoch)
+            ),
+        })),
+    }
+}
+
+/// Reports of the latest import of every dataset, keyed by the dataset's name
+pub fn latest_imports(
+    data: web::Data<Arc<Mutex<data::AppState>>>,
+    _auth: user::SlimUser,
+) -> HttpResponse {
+    // only clone the `Arc`s while holding the lock, the reports may be large to serialize
+    let reports: Vec<(String, Arc<ImportReport>)> = data
+        .lock()
+        .unwrap()
+        .datasets
+        .iter()
+        .map(|(name, dataset)| (name.clone(), dataset.latest.clone()))
+        .collect();
+    let reports: BTreeMap<&str, &ImportReport> = reports
+        .iter()
+        .map(|(name, report)| (name.as_str(), &**report))
+        .collect();
+    HttpResponse::Ok().json(reports)
+}
+
 pub fn login(
     auth_user: web::Json<user::AuthUser>,
     data: web::Data<Arc<Mutex<data::AppState>>>,
@@ -0,0 +1,324 @@
+//! Module for joining auxiliary datasets onto the rows of a primary one
+//!
+//! Prices or stock often live in separate csvs from the product master. They are configured as
+//! datasets of their own, so they are loaded, refreshed and gated independently, and joined onto
+//! the primary rows whenever either side changes:
+//!
+//! ```yaml
+//! joins:
+//!   - dataset: prices
+//!     on: sku         # column(s) matched against the key of `prices`, defaults to our own key
+//!     kind: inner     # drop rows without a price, defaults to `left`
+//!     prefix: price_  # prepended to the joined columns
+//! ```
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::data::Dataset;
+use crate::format::Format;
+use crate::model::{Key, Product, Record};
+use crate::settings::{Csv, KeyColumns, Mode};
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Join {
+    /// name of the dataset joined onto ours
+    pub dataset: String,
+    /// columns of our rows matched against the key of the joined dataset
+    pub on: Option<KeyColumns>,
+    #[serde(default)]
+    pub kind: JoinKind,
+    /// prepended to the names of the joined columns, to keep them apart from our own
+    #[serde(default)]
+    pub prefix: String,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JoinKind {
+    /// rows without a match are served as they are
+    Left,
+    /// rows without a match are not served at all
+    Inner,
+}
+
+impl Default for JoinKind {
+    fn default() -> Self {
+        JoinKind::Left
+    }
+}
+
+impl Join {
+    /// The columns of our rows matched against the key of the joined dataset
+    pub fn columns<'a>(&'a self, source: &'a Csv) -> Vec<&'a str> {
+        self.on.as_ref().unwrap_or(&source.key).columns()
+    }
+
+    /// Check the join of `source` against the other configured `datasets`
+    pub fn validate(&self, source: &Csv, datasets: &[Csv]) -> Result<(), String> {
+        if self.dataset == source.name {
+            return Err("a dataset can't be joined onto itself".into());
+        }
+        let other = datasets
+            .iter()
+            .find(|other| other.name == self.dataset)
+            .ok_or_else(|| format!("unknown dataset {}", self.dataset))?;
+        if !other.joins.is_empty() {
+            return Err(format!(
+                "{} has joins of its own, which can't be joined in turn",
+                other.name
+            ));
+        }
+        let columns = self.columns(source);
+        if columns.len() != other.key.columns().len() {
+            return Err(format!(
+                "{:?} doesn't match the key {:?} of {}",
+                columns,
+                other.key.columns(),
+                other.name
+            ));
+        }
+        // otherwise the columns are only known once the data is loaded, see `apply`
+        let known: Option<Vec<&str>> = match source.mode {
+            Mode::Product => Some(Product::COLUMNS.to_vec()),
+            Mode::Dynamic if source.format == Format::Csv && !source.dialect.columns.is_empty() => {
+                Some(source.dialect.columns.iter().map(String::as_str).collect())
+            }
+            Mode::Dynamic => None,
+        };
+        if let Some(known) = known {
+            if let Some(column) = columns.iter().find(|column| !known.contains(column)) {
+                return Err(format!("unknown column {}, there are {:?}", column, known));
+            }
+        }
+        Ok(())
+    }
+
+    /// The first of our `columns` missing from the header row of our data, if any. Joining on it
+    /// would leave every single row without a match.
+    fn missing<'a>(&self, dataset: &Dataset, columns: &[&'a str]) -> Option<&'a str> {
+        if dataset.csv.mode == Mode::Product {
+            // a product has all the product columns, `validate` made sure we join on those
+            return None;
+        }
+        columns
+            .iter()
+            .find(|column| {
+                !dataset
+                    .report
+                    .headers
+                    .iter()
+                    .any(|header| header == *column)
+            })
+            .cloned()
+    }
+}
+
+/// Join the datasets configured in the `joins` of `dataset` onto its `raw` rows
+pub fn apply(dataset: &Dataset, datasets: &HashMap<String, Dataset>) -> HashMap<Key, Record> {
+    let source = &dataset.csv;
+    let joins: Vec<(&Join, Vec<&str>, &Dataset)> = source
+        .joins
+        .iter()
+        .filter_map(|join| {
+            let other = datasets.get(&join.dataset)?;
+            let columns = join.columns(source);
+            if let Some(column) = join.missing(dataset, &columns) {
+                eprintln!(
+                    "Not joining {} onto {}, it has no column {}",
+                    join.dataset, source.name, column
+                );
+                return None;
+            }
+            Some((join, columns, other))
+        })
+        .collect();
+    let mut map = HashMap::with_capacity(dataset.raw.len());
+
+    'rows: for (key, row) in dataset.raw.iter() {
+        let mut record = row.clone();
+        for (join, columns, other) in &joins {
+            let matched = lookup(row, columns).and_then(|key| other.raw.get(&key));
+            match matched {
+                Some(joined) => {
+                    let key_columns = other.csv.key.columns();
+                    for (column, value) in joined {
+                        if key_columns.contains(&column.as_str()) {
+                            continue;
+                        }
+                        // our own columns take precedence over joined ones of the same name
+                        record
+                            .entry(format!("{}{}", join.prefix, column))
+                            .or_insert_with(|| value.clone());
+                    }
+                }
+                None if join.kind == JoinKind::Inner => continue 'rows,
+                None => {}
+            }
+        }
+        map.insert(key.clone(), record);
+    }
+
+    map
+}
+
+/// The key to look up in a joined dataset, built from the `columns` of `row` the same way keys
+/// are built when parsing, i.e. with strings trimmed. Rows with a missing or empty value have
+/// nothing to join.
+fn lookup(row: &Record, columns: &[&str]) -> Option<Key> {
+    columns
+        .iter()
+        .map(|column| match row.get(*column)? {
+            Value::Null => None,
+            Value::String(value) => match value.trim() {
+                "" => None,
+                value => Some(value.to_string()),
+            },
+            value => Some(value.to_string()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use crate::source::StaticSource;
+
+    fn source(settings: Value) -> Csv {
+        serde_json::from_value(settings).unwrap()
+    }
+
+    fn datasets(joins: Value, products_csv: &str) -> HashMap<String, Dataset> {
+        let products = source(json!({
+            "name": "products",
+            "uri": "products.csv",
+            "mode": "dynamic",
+            "key": ["id"],
+            "joins": joins,
+        }));
+        let prices = source(json!({
+            "name": "prices",
+            "uri": "prices.csv",
+            "mode": "dynamic",
+            "key": ["sku"],
+        }));
+        let prices_csv = "sku,price,name\nA,10,Price of A\n42,5,Price of 42\n";
+        vec![(products, products_csv), (prices, prices_csv)]
+            .into_iter()
+            .map(|(source, csv)| {
+                let dataset =
+                    Dataset::open_with(source, Arc::new(StaticSource(csv.into()))).unwrap();
+                (dataset.csv.name.clone(), dataset)
+            })
+            .collect()
+    }
+
+    fn key(id: &str) -> Key {
+        vec![id.to_string()]
+    }
+
+    const PRODUCTS: &str = "id,sku,name\n1,A,Apple\n2,B,Banana\n3, 42 ,Cherry\n";
+
+    #[test]
+    fn keeps_rows_without_a_match_in_left_joins() {
+        let datasets = datasets(json!([{ "dataset": "prices", "on": "sku" }]), PRODUCTS);
+        let map = apply(&datasets["products"], &datasets);
+        assert_eq!(map.len(), 3);
+        assert_eq!(map[&key("1")]["price"], json!("10"));
+        assert_eq!(map[&key("2")].get("price"), None);
+        // the key of the joined dataset is ours already
+        assert_eq!(map[&key("1")]["sku"], json!("A"));
+    }
+
+    #[test]
+    fn drops_rows_without_a_match_in_inner_joins() {
+        let joins = json!([{ "dataset": "prices", "on": "sku", "kind": "inner" }]);
+        let datasets = datasets(joins, PRODUCTS);
+        let map = apply(&datasets["products"], &datasets);
+        let mut keys: Vec<&Key> = map.keys().collect();
+        keys.sort();
+        assert_eq!(keys, vec![&key("1"), &key("3")]);
+    }
+
+    #[test]
+    fn prefixes_the_joined_columns() {
+        let joins = json!([{ "dataset": "prices", "on": "sku", "prefix": "price_" }]);
+        let datasets = datasets(joins, PRODUCTS);
+        let map = apply(&datasets["products"], &datasets);
+        let row = &map[&key("1")];
+        let columns: Vec<&str> = row.keys().map(String::as_str).collect();
+        assert_eq!(
+            columns,
+            vec!["id", "sku", "name", "price_price", "price_name"]
+        );
+        assert_eq!(row["name"], json!("Apple"));
+        assert_eq!(row["price_name"], json!("Price of A"));
+    }
+
+    #[test]
+    fn prefers_our_own_columns() {
+        let datasets = datasets(json!([{ "dataset": "prices", "on": "sku" }]), PRODUCTS);
+        let map = apply(&datasets["products"], &datasets);
+        assert_eq!(map[&key("1")]["name"], json!("Apple"));
+        assert_eq!(map[&key("1")]["price"], json!("10"));
+    }
+
+    #[test]
+    fn looks_up_trimmed_and_typed_values() {
+        let row = |value: Value| json!({ "sku": value }).as_object().unwrap().clone();
+        assert_eq!(lookup(&row(json!(" A ")), &["sku"]), Some(key("A")));
+        assert_eq!(lookup(&row(json!(42)), &["sku"]), Some(key("42")));
+        assert_eq!(lookup(&row(json!(true)), &["sku"]), Some(key("true")));
+        assert_eq!(lookup(&row(json!("  ")), &["sku"]), None);
+        assert_eq!(lookup(&row(Value::Null), &["sku"]), None);
+        assert_eq!(lookup(&row(json!("A")), &["sku", "size"]), None);
+
+        // trimmed csv values match, see product 3
+        let datasets = datasets(json!([{ "dataset": "prices", "on": "sku" }]), PRODUCTS);
+        let map = apply(&datasets["products"], &datasets);
+        assert_eq!(map[&key("3")]["price"], json!("5"));
+    }
+
+    #[test]
+    fn skips_joins_on_missing_columns() {
+        let joins = json!([{ "dataset": "prices", "on": "ksu", "kind": "inner" }]);
+        let datasets = datasets(joins, PRODUCTS);
+        let map = apply(&datasets["products"], &datasets);
+        assert_eq!(map.len(), 3);
+        assert_eq!(map[&key("1")].get("price"), None);
+    }
+
+    #[test]
+    fn validates_the_columns_joined_on() {
+        let prices = source(json!({ "name": "prices", "uri": "prices.csv", "key": "sku" }));
+        let join = |on: &str| -> Join {
+            serde_json::from_value(json!({ "dataset": "prices", "on": on })).unwrap()
+        };
+
+        let product = source(json!({ "name": "products", "uri": "products.csv" }));
+        let datasets = vec![product.clone(), prices.clone()];
+        assert!(join("title").validate(&product, &datasets).is_ok());
+        assert!(join("sku").validate(&product, &datasets).is_err());
+
+        let named = source(json!({
+            "name": "products",
+            "uri": "products.csv",
+            "mode": "dynamic",
+            "dialect": { "has_headers": false, "columns": ["id", "sku"] },
+        }));
+        let datasets = vec![named.clone(), prices.clone()];
+        assert!(join("sku").validate(&named, &datasets).is_ok());
+        assert!(join("ksu").validate(&named, &datasets).is_err());
+
+        // columns only known once loaded
+        let dynamic =
+            source(json!({ "name": "products", "uri": "products.csv", "mode": "dynamic" }));
+        let datasets = vec![dynamic.clone(), prices];
+        assert!(join("ksu").validate(&dynamic, &datasets).is_ok());
+    }
+}
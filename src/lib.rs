@@ -1,9 +1,22 @@
+pub mod changes;
+pub mod compression;
 pub mod data;
+pub mod dialect;
+pub mod encoding;
 pub mod error;
+pub mod format;
+pub mod gates;
 pub mod handler;
+pub mod join;
 pub mod jwt;
 pub mod middleware;
 pub mod model;
+pub mod refresh;
+pub mod report;
 pub mod routes;
+pub mod schema;
 pub mod settings;
+pub mod snapshot;
+pub mod source;
 pub mod user;
+pub mod watch;
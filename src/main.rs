@@ -4,8 +4,10 @@ use std::process;
 use csvbuttler::data;
 use csvbuttler::error;
 use csvbuttler::handler;
+use csvbuttler::refresh;
 use csvbuttler::routes;
 use csvbuttler::settings::Settings;
+use csvbuttler::watch;
 
 use actix_identity::{CookieIdentityPolicy, IdentityService};
 use actix_web::middleware::{Compress, DefaultHeaders, Logger};
@@ -27,12 +29,23 @@ fn run() -> std::io::Result<()> {
     env_logger::init();
     let log_fmt = "%a '%r' %s %b '%{Referer}i' '%{User-Agent}i' %D";
     let state = data::AppState::new()?;
+    refresh::spawn(state.clone())?;
+    watch::spawn(state.clone())?;
     let settings = Settings::new().map_err(error::Error::ConfigError)?;
     let server_str = build_server_str(&settings);
+    let datasets: Vec<(String, Vec<String>)> = settings
+        .datasets()
+        .map_err(error::Error::ConfigError)?
+        .into_iter()
+        .map(|dataset| {
+            let key = dataset.key.columns().into_iter().map(String::from).collect();
+            (dataset.name, key)
+        })
+        .collect();
 
     let mut listenfd = ListenFd::from_env();
     let mut server = HttpServer::new(move || {
-        App::new()
+        let mut app = App::new()
             // getting a reference to the data
             .data(state.clone())
             .data(CsrfTokenGenerator::new(
@@ -53,16 +66,23 @@ fn run() -> std::io::Result<()> {
             ))
             // this is our root handler
             .route("/", web::get().to(handler::index))
-            .service(
-                web::scope("/products")
-                    .wrap(DefaultHeaders::new().header("Cache-Control", "max-age=3600"))
-                    .configure(routes::config),
-            )
             .service(
                 web::resource("/auth")
                     .route(web::post().to(handler::login))
                     .route(web::delete().to(handler::logout)),
             )
+            .service(web::scope("/admin").configure(routes::admin));
+
+        // every dataset is served under its own name
+        for (name, key) in &datasets {
+            app = app.service(
+                web::scope(&format!("/{}", name))
+                    .data(routes::DatasetName(name.clone()))
+                    .wrap(DefaultHeaders::new().header("Cache-Control", "max-age=3600"))
+                    .configure(|cfg| routes::config(cfg, key)),
+            );
+        }
+        app
     });
 
     server = if let Some(l) = listenfd.take_tcp_listener(0).unwrap() {
@@ -3,6 +3,13 @@
 use actix_web::{Error, HttpRequest, HttpResponse, Responder};
 use serde::{Deserialize, Serialize};
 use serde_json;
+use serde_json::{Map, Value};
+
+/// A single row, mapping column names to values in the order of the csv's columns
+pub type Record = Map<String, Value>;
+
+/// The values of a row's key columns, in the order the key columns are configured
+pub type Key = Vec<String>;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Product {
@@ -13,6 +20,19 @@ pub struct Product {
     pub price: String,
 }
 
+impl Product {
+    /// Names of the columns a `Product` is deserialized from
+    pub const COLUMNS: [&'static str; 5] = ["id", "title", "description", "brand", "price"];
+
+    /// Convert into a generic `Record` so it can be stored alongside schema-less rows
+    pub fn into_record(self) -> Record {
+        match serde_json::to_value(self) {
+            Ok(Value::Object(record)) => record,
+            _ => unreachable!("a Product always serializes into an object"),
+        }
+    }
+}
+
 impl Responder for Product {
     type Error = Error;
     type Future = Result<HttpResponse, Error>;
@@ -26,13 +46,3 @@ impl Responder for Product {
             .body(body))
     }
 }
-
-pub fn error_product() -> Product {
-    Product {
-        id: 0,
-        title: "Foo".to_string(),
-        description: None,
-        brand: "Foo".to_string(),
-        price: "Bar".to_string(),
-    }
-}
@@ -0,0 +1,114 @@
+//! Module for periodically refreshing the csv data in the background
+//!
+//! The data is re-fetched (or re-read from disk) and parsed on a separate thread. Only when that
+//! succeeded the new map is swapped into the `AppState`, so a failing upstream never leaves us
+//! without data.
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+use chrono::Local;
+use cron;
+
+use crate::data::{self, StateType};
+use crate::error::Error;
+use crate::settings::{Csv, Refresh};
+
+/// When to run the next refresh
+pub enum Schedule {
+    Interval(Duration),
+    Cron(cron::Schedule),
+}
+
+impl Schedule {
+    /// Build a `Schedule` from the `refresh` settings of a dataset. Returns `None` if neither an
+    /// `interval` nor a `cron` expression is configured.
+    pub fn from_settings(refresh: &Refresh) -> Result<Option<Self>, Error> {
+        match (refresh.interval, &refresh.cron) {
+            (Some(_), Some(_)) => Err(Error::Other(
+                "Only one of refresh.interval and refresh.cron may be set".into(),
+            )),
+            (Some(0), None) => Err(Error::Other(
+                "refresh.interval must be greater than 0".into(),
+            )),
+            (Some(secs), None) => Ok(Some(Schedule::Interval(Duration::from_secs(secs)))),
+            (None, Some(expr)) => cron::Schedule::from_str(expr)
+                .map(|schedule| Some(Schedule::Cron(schedule)))
+                .map_err(|e| Error::Other(format!("Invalid refresh.cron: {}", e))),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Time to wait until the next refresh is due. `None` means there is no upcoming date.
+    pub fn next_delay(&self) -> Option<Duration> {
+        match self {
+            Schedule::Interval(duration) => Some(*duration),
+            Schedule::Cron(schedule) => schedule
+                .upcoming(Local)
+                .next()
+                .map(|next| (next - Local::now()).to_std().unwrap_or_default()),
+        }
+    }
+}
+
+/// Spawn a background refresh thread for every dataset that has a schedule configured, or that
+/// was restored from a snapshot and needs to be refreshed right away
+pub fn spawn(state: StateType) -> Result<Vec<thread::JoinHandle<()>>, Error> {
+    let sources: Vec<(Csv, bool)> = state
+        .lock()
+        .unwrap()
+        .datasets
+        .values()
+        .map(|dataset| (dataset.csv.clone(), dataset.stale))
+        .collect();
+
+    let mut handles = Vec::new();
+    for (source, stale) in sources {
+        let schedule = match &source.refresh {
+            Some(refresh) => {
+                let schedule = Schedule::from_settings(refresh)?;
+                if schedule.is_some() {
+                    println!("Refreshing {} {:?}", &source.name, refresh);
+                }
+                schedule
+            }
+            None => None,
+        };
+
+        if schedule.is_none() && !stale {
+            continue;
+        }
+
+        let state = state.clone();
+        let handle = thread::Builder::new()
+            .name(format!("refresh-{}", &source.name))
+            .spawn(move || {
+                if stale {
+                    refresh(&state, &source);
+                }
+                if let Some(schedule) = schedule {
+                    while let Some(delay) = schedule.next_delay() {
+                        thread::sleep(delay);
+                        refresh(&state, &source);
+                    }
+                }
+            })?;
+        handles.push(handle);
+    }
+    Ok(handles)
+}
+
+/// Load the dataset once and swap it into the `AppState` if that succeeded
+pub fn refresh(state: &StateType, source: &Csv) {
+    match data::reload(state, &source.name) {
+        Ok(Some(reloaded)) => println!(
+            "Refreshed {} rows of {} from {} ({} added, {} removed)",
+            reloaded.rows, source.name, source.uri, reloaded.added, reloaded.removed
+        ),
+        Ok(None) => println!("{} unchanged, keeping current data", source.uri),
+        Err(e) => eprintln!(
+            "Refreshing {} failed, keeping current data: {}",
+            source.uri, e
+        ),
+    }
+}
@@ -0,0 +1,113 @@
+//! Module holding the report of an import, i.e. what happened to the rows of a single load
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::model::Key;
+
+/// Summary of a single load of a dataset, including every row that was rejected
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ImportReport {
+    pub dataset: String,
+    pub uri: String,
+    pub imported_at: DateTime<Utc>,
+    pub headers: Vec<String>,
+    /// number of data rows read, not counting the header
+    pub rows: usize,
+    pub accepted: usize,
+    pub rejected: Vec<Rejection>,
+    /// rows whose key was taken already, resolved according to the dataset's `duplicates` policy
+    pub duplicates: Vec<Duplicate>,
+    /// statistics of every file the dataset was merged from
+    pub files: Vec<FileStats>,
+    /// why the import was refused by the safety gates and the previous data is still served
+    pub refused: Option<String>,
+}
+
+/// A row that did not make it into the dataset
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Rejection {
+    pub file: Option<String>,
+    pub line: u64,
+    pub column: Option<String>,
+    pub value: Option<String>,
+    pub reason: String,
+}
+
+/// A row whose key was taken by an earlier row
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Duplicate {
+    pub file: Option<String>,
+    pub line: u64,
+    pub key: Key,
+}
+
+/// What a single file contributed to the dataset
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FileStats {
+    pub file: String,
+    pub rows: usize,
+    pub accepted: usize,
+    pub rejected: usize,
+    /// rows whose key was taken already
+    pub duplicates: usize,
+}
+
+impl ImportReport {
+    pub fn new(dataset: &str, uri: &str) -> Self {
+        ImportReport {
+            dataset: dataset.into(),
+            uri: uri.into(),
+            imported_at: Utc::now(),
+            headers: Vec::new(),
+            rows: 0,
+            accepted: 0,
+            rejected: Vec::new(),
+            duplicates: Vec::new(),
+            files: Vec::new(),
+            refused: None,
+        }
+    }
+
+    pub fn reject(
+        &mut self,
+        line: u64,
+        column: Option<&str>,
+        value: Option<&str>,
+        reason: impl Into<String>,
+    ) {
+        self.rejected.push(Rejection {
+            file: None,
+            line,
+            column: column.map(String::from),
+            value: value.map(String::from),
+            reason: reason.into(),
+        });
+    }
+
+    pub fn duplicate(&mut self, line: u64, key: &Key) {
+        self.duplicates.push(Duplicate {
+            file: None,
+            line,
+            key: key.clone(),
+        });
+    }
+
+    /// One line summary for the logs
+    pub fn summary(&self) -> String {
+        let files = if self.files.len() > 1 {
+            format!(", {} files", self.files.len())
+        } else {
+            String::new()
+        };
+        format!(
+            "Imported {} of {} rows into {} from {} ({} rejected, {} duplicates{})",
+            self.accepted,
+            self.rows,
+            self.dataset,
+            self.uri,
+            self.rejected.len(),
+            self.duplicates.len(),
+            files
+        )
+    }
+}
@@ -1,18 +1,81 @@
+use actix_web::middleware::DefaultHeaders;
 use actix_web::web;
 
 use crate::handler;
 use crate::middleware::cors;
 
-pub fn config(cfg: &mut web::ServiceConfig) {
+/// Name of the dataset served by a scope, handed to the handlers as scope data
+pub struct DatasetName(pub String);
+
+/// Routes of a single dataset. The scope these are configured on is expected to provide the
+/// `DatasetName` as data.
+///
+/// Rows are looked up by one path segment per `key` column, e.g. `/{country}/{sku}`. The changes
+/// feed is served under `/_changes`, which takes precedence over a key of that name. Unlike the
+/// rows it must not be cached, that would hide the latest changes.
+pub fn config(cfg: &mut web::ServiceConfig, key: &[String]) {
     cfg.service(
+        web::resource("/_changes")
+            .name("changes")
+            .wrap(DefaultHeaders::new().header("Cache-Control", "no-store"))
+            .wrap(cors())
+            .route(web::get().to(handler::changes)),
+    )
+    .service(
         // TODO: paginated list of products
         // .service(web::resource("")
         //     .route(web::get().to_async(handler::tbc))
         // )
         // .route("/", web::get().to(handler::index))
-        web::resource("/{id}")
+        web::resource(&key_pattern(key))
             .name("product")
             .wrap(cors())
             .route(web::get().to_async(handler::product)),
     );
 }
+
+/// Administrative routes, all of them require authentication
+pub fn admin(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/imports/latest")
+            .name("latest_imports")
+            .route(web::get().to(handler::latest_imports)),
+    );
+}
+
+/// Build the resource pattern from the key columns. Columns that would not make for a valid
+/// segment name are replaced by their position.
+fn key_pattern(key: &[String]) -> String {
+    key.iter()
+        .enumerate()
+        .map(|(i, column)| {
+            if !column.is_empty() && column.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                format!("/{{{}}}", column)
+            } else {
+                format!("/{{key{}}}", i)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(columns: &[&str]) -> Vec<String> {
+        columns.iter().map(|column| column.to_string()).collect()
+    }
+
+    #[test]
+    fn builds_a_segment_per_key_column() {
+        assert_eq!(key_pattern(&key(&["id"])), "/{id}");
+        assert_eq!(key_pattern(&key(&["country", "sku"])), "/{country}/{sku}");
+        assert_eq!(key_pattern(&key(&["product_id"])), "/{product_id}");
+    }
+
+    #[test]
+    fn replaces_invalid_segment_names_by_their_position() {
+        assert_eq!(key_pattern(&key(&["country", "item no."])), "/{country}/{key1}");
+        assert_eq!(key_pattern(&key(&["", "a/b", "{x}"])), "/{key0}/{key1}/{key2}");
+    }
+}
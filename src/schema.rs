@@ -0,0 +1,340 @@
+//! Module holding the declarative column schema of a dataset
+//!
+//! In `Mode::Dynamic` every cell is a string unless the dataset's `schema` declares a type for its
+//! column, in which case it is converted into the matching JSON type:
+//!
+//! ```yaml
+//! schema:
+//!   price:
+//!     type: decimal
+//!     locale: de        # 1.234,56
+//!   available:
+//!     type: bool
+//!     default: "false"  # used for empty cells
+//!   tags:
+//!     type: list
+//!     separator: "|"
+//!     items: string
+//! ```
+use std::collections::HashMap;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use derive_more::Display;
+use serde::Deserialize;
+use serde_json::{Number, Value};
+
+/// Column name to column definition
+pub type Schema = HashMap<String, Column>;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Type {
+    String,
+    Int,
+    Decimal,
+    Bool,
+    Date,
+    Datetime,
+    Enum,
+    List,
+}
+
+impl Default for Type {
+    fn default() -> Self {
+        Type::String
+    }
+}
+
+/// Decimal and thousands separators used when parsing numbers
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    /// 1,234.56
+    En,
+    /// 1.234,56
+    De,
+    /// 1 234,56
+    Fr,
+    /// 1'234.56
+    Ch,
+}
+
+impl Locale {
+    fn separators(self) -> (char, char) {
+        match self {
+            Locale::En => ('.', ','),
+            Locale::De => (',', '.'),
+            Locale::Fr => (',', ' '),
+            Locale::Ch => ('.', '\''),
+        }
+    }
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Locale::En
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Column {
+    #[serde(rename = "type", default)]
+    pub kind: Type,
+    /// whether empty cells become `null` rather than failing the row
+    #[serde(default = "default_nullable")]
+    pub nullable: bool,
+    /// raw value used for empty cells, converted like any other cell
+    pub default: Option<String>,
+    #[serde(default)]
+    pub locale: Locale,
+    /// `chrono` format of `date` and `datetime` columns. Defaults to `%Y-%m-%d` and RFC 3339.
+    pub format: Option<String>,
+    /// allowed values of an `enum` column
+    #[serde(default)]
+    pub values: Vec<String>,
+    /// separator of a `list` column, defaults to `,`
+    pub separator: Option<String>,
+    /// type of the items of a `list` column
+    #[serde(default)]
+    pub items: Type,
+}
+
+fn default_nullable() -> bool {
+    true
+}
+
+/// A cell that could not be converted into the type its column declares
+#[derive(Debug, Display)]
+#[display(fmt = "column {}: {:?} {}", column, value, reason)]
+pub struct ConversionError {
+    pub column: String,
+    pub value: String,
+    pub reason: String,
+}
+
+impl Column {
+    /// Check the definition for inconsistencies that would make every row fail
+    pub fn validate(&self) -> Result<(), String> {
+        if self.kind == Type::Enum && self.values.is_empty() {
+            return Err("enum columns need a list of values".into());
+        }
+        if self.kind == Type::List && (self.items == Type::List || self.items == Type::Enum) {
+            return Err("list items can't be lists or enums".into());
+        }
+        if self.kind == Type::List && self.separator.as_ref().map_or(false, String::is_empty) {
+            return Err("the separator of list columns can't be empty".into());
+        }
+        if let Some(default) = &self.default {
+            self.convert(default)
+                .map_err(|reason| format!("invalid default: {}", reason))?;
+        }
+        Ok(())
+    }
+
+    /// Convert a raw cell into the declared type
+    pub fn convert(&self, raw: &str) -> Result<Value, String> {
+        let trimmed = raw.trim();
+        if trimmed.is_empty() {
+            return match &self.default {
+                Some(default) => self.convert(default),
+                None if self.nullable => Ok(Value::Null),
+                None => Err("is missing".into()),
+            };
+        }
+
+        match self.kind {
+            Type::String => Ok(Value::String(raw.into())),
+            Type::List => {
+                let separator = self.separator.as_ref().map(String::as_str).unwrap_or(",");
+                trimmed
+                    .split(separator)
+                    .map(|item| self.scalar(self.items, item.trim()))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(Value::Array)
+            }
+            kind => self.scalar(kind, trimmed),
+        }
+    }
+
+    fn scalar(&self, kind: Type, raw: &str) -> Result<Value, String> {
+        match kind {
+            Type::String => Ok(Value::String(raw.into())),
+            Type::Int => self
+                .number(raw)
+                .and_then(|number| number.parse::<i64>().ok())
+                .map(Value::from)
+                .ok_or_else(|| "is not an integer".into()),
+            Type::Decimal => self
+                .number(raw)
+                .and_then(|number| number.parse::<f64>().ok())
+                .and_then(Number::from_f64)
+                .map(Value::Number)
+                .ok_or_else(|| "is not a decimal".into()),
+            Type::Bool => match raw.to_lowercase().as_str() {
+                "true" | "1" | "yes" | "y" => Ok(Value::Bool(true)),
+                "false" | "0" | "no" | "n" => Ok(Value::Bool(false)),
+                _ => Err("is not a boolean".into()),
+            },
+            Type::Date => {
+                let format = self.format.as_ref().map(String::as_str).unwrap_or("%Y-%m-%d");
+                NaiveDate::parse_from_str(raw, format)
+                    .map(|date| Value::String(date.format("%Y-%m-%d").to_string()))
+                    .map_err(|e| format!("is not a date: {}", e))
+            }
+            Type::Datetime => match &self.format {
+                Some(format) => NaiveDateTime::parse_from_str(raw, format)
+                    .map(|datetime| Value::String(datetime.format("%Y-%m-%dT%H:%M:%S").to_string())),
+                None => DateTime::parse_from_rfc3339(raw)
+                    .map(|datetime| Value::String(datetime.to_rfc3339())),
+            }
+            .map_err(|e| format!("is not a datetime: {}", e)),
+            Type::Enum => {
+                if self.values.iter().any(|value| value == raw) {
+                    Ok(Value::String(raw.into()))
+                } else {
+                    Err(format!("is not one of {:?}", self.values))
+                }
+            }
+            Type::List => Err("lists can't be nested".into()),
+        }
+    }
+
+    /// Normalize a number according to the `locale`, so it can be parsed by `str::parse`.
+    /// Thousands separators are only accepted between groups of three digits before the decimal
+    /// separator. Anything else, like `12,99` in the `en` locale, is not a number.
+    fn number(&self, raw: &str) -> Option<String> {
+        let (decimal, thousands) = self.locale.separators();
+        let unsigned = raw.trim_start_matches(|c| c == '-' || c == '+');
+        let sign = &raw[..raw.len() - unsigned.len()];
+
+        let mut parts = unsigned.splitn(2, decimal);
+        let integer = parts.next().unwrap_or_default();
+        let fraction = parts.next();
+
+        let groups: Vec<&str> = integer.split(thousands).collect();
+        let grouped = groups.len() == 1
+            || ((1..=3).contains(&groups[0].len())
+                && groups[1..].iter().all(|group| group.len() == 3));
+        if !grouped || fraction.map_or(false, |fraction| fraction.contains(thousands)) {
+            return None;
+        }
+
+        let mut number = format!("{}{}", sign, groups.concat());
+        if let Some(fraction) = fraction {
+            number.push('.');
+            number.push_str(fraction);
+        }
+        Some(number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn column(definition: Value) -> Column {
+        serde_json::from_value(definition).unwrap()
+    }
+
+    #[test]
+    fn converts_numbers_in_the_default_locale() {
+        let int = column(json!({ "type": "int" }));
+        assert_eq!(int.convert("42"), Ok(json!(42)));
+        assert_eq!(int.convert(" -1,234 "), Ok(json!(-1234)));
+        assert_eq!(int.convert("1,234,567"), Ok(json!(1_234_567)));
+        assert!(int.convert("1.5").is_err());
+        assert!(int.convert("abc").is_err());
+
+        let decimal = column(json!({ "type": "decimal" }));
+        assert_eq!(decimal.convert("12.99"), Ok(json!(12.99)));
+        assert_eq!(decimal.convert("1,234.5"), Ok(json!(1234.5)));
+        assert_eq!(decimal.convert(".5"), Ok(json!(0.5)));
+        assert_eq!(decimal.convert("1.5e3"), Ok(json!(1500.0)));
+    }
+
+    #[test]
+    fn rejects_misplaced_thousands_separators() {
+        let decimal = column(json!({ "type": "decimal" }));
+        assert!(decimal.convert("12,99").is_err());
+        assert!(decimal.convert("1,2345.6").is_err());
+        assert!(decimal.convert("1234,567").is_err());
+        assert!(decimal.convert(",123").is_err());
+        assert!(decimal.convert("1,234.5,6").is_err());
+        assert!(decimal.convert("1,,234").is_err());
+
+        let int = column(json!({ "type": "int", "locale": "de" }));
+        assert!(int.convert("1.5").is_err());
+        assert!(int.convert("12.34").is_err());
+        assert_eq!(int.convert("1.500"), Ok(json!(1500)));
+    }
+
+    #[test]
+    fn converts_numbers_in_other_locales() {
+        let de = column(json!({ "type": "decimal", "locale": "de" }));
+        assert_eq!(de.convert("12,99"), Ok(json!(12.99)));
+        assert_eq!(de.convert("1.234,56"), Ok(json!(1234.56)));
+        assert!(de.convert("1,234.56").is_err());
+
+        let fr = column(json!({ "type": "decimal", "locale": "fr" }));
+        assert_eq!(fr.convert("1 234,5"), Ok(json!(1234.5)));
+
+        let ch = column(json!({ "type": "decimal", "locale": "ch" }));
+        assert_eq!(ch.convert("1'234.5"), Ok(json!(1234.5)));
+    }
+
+    #[test]
+    fn handles_empty_cells() {
+        let nullable = column(json!({ "type": "int" }));
+        assert_eq!(nullable.convert("  "), Ok(Value::Null));
+
+        let required = column(json!({ "type": "int", "nullable": false }));
+        assert!(required.convert("").is_err());
+
+        let defaulted = column(json!({ "type": "bool", "default": "false" }));
+        assert_eq!(defaulted.convert(""), Ok(json!(false)));
+    }
+
+    #[test]
+    fn converts_other_types() {
+        let string = column(json!({}));
+        assert_eq!(string.convert(" a "), Ok(json!(" a ")));
+
+        let bool = column(json!({ "type": "bool" }));
+        assert_eq!(bool.convert("Yes"), Ok(json!(true)));
+        assert_eq!(bool.convert("0"), Ok(json!(false)));
+        assert!(bool.convert("maybe").is_err());
+
+        let date = column(json!({ "type": "date", "format": "%d.%m.%Y" }));
+        assert_eq!(date.convert("24.12.2019"), Ok(json!("2019-12-24")));
+        assert!(date.convert("2019-12-24").is_err());
+
+        let datetime = column(json!({ "type": "datetime" }));
+        assert_eq!(
+            datetime.convert("2019-12-24T18:00:00+01:00"),
+            Ok(json!("2019-12-24T18:00:00+01:00"))
+        );
+
+        let kind = column(json!({ "type": "enum", "values": ["new", "used"] }));
+        assert_eq!(kind.convert("used"), Ok(json!("used")));
+        assert!(kind.convert("broken").is_err());
+
+        let list = column(json!({ "type": "list", "separator": "|", "items": "int" }));
+        assert_eq!(list.convert("1| 2 |3"), Ok(json!([1, 2, 3])));
+        assert!(list.convert("1|x").is_err());
+    }
+
+    #[test]
+    fn validates_list_columns() {
+        assert!(column(json!({ "type": "list" })).validate().is_ok());
+        assert!(column(json!({ "type": "list", "separator": "|" }))
+            .validate()
+            .is_ok());
+        assert!(column(json!({ "type": "list", "separator": "" }))
+            .validate()
+            .is_err());
+        assert!(column(json!({ "type": "list", "items": "list" }))
+            .validate()
+            .is_err());
+    }
+}
@@ -1,10 +1,22 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::path::Path;
 
 use config::{Config, ConfigError, Environment, File};
 use serde::Deserialize;
 use structopt::StructOpt;
 
+use crate::dialect::Dialect;
+use crate::encoding;
+use crate::format::Format;
+use crate::gates::Gates;
+use crate::join::Join;
+use crate::schema::Schema;
+use crate::source;
+
 #[derive(StructOpt, Debug)]
 #[structopt(name = "csvbuttler", about = "serves data from csv files")]
 struct Cli {
@@ -34,25 +46,436 @@ pub struct Default {
     pub https: bool,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+/// A single csv source. The `name` is used as the path prefix the dataset is served under,
+/// `key` names the column (or columns, for composite keys) used to look up rows.
+#[derive(Clone, Deserialize)]
 pub struct Csv {
+    #[serde(default = "default_name")]
+    pub name: String,
+    #[serde(default = "default_key")]
+    pub key: KeyColumns,
+    #[serde(default)]
+    pub mode: Mode,
+    /// format of the data, csv unless stated otherwise
+    #[serde(default)]
+    pub format: Format,
+    /// character encoding of text formats, UTF-8 unless stated otherwise, see `encoding`
+    pub encoding: Option<String>,
+    /// sheet and header row of `xlsx` and `ods` sources
+    #[serde(default)]
+    pub spreadsheet: Spreadsheet,
+    /// how rows with a key that is taken already are handled
+    #[serde(default)]
+    pub duplicates: Duplicates,
+    /// column types, only used in `Mode::Dynamic`
+    pub schema: Option<Schema>,
+    /// checks a reload has to pass before it replaces the current data
+    #[serde(default)]
+    pub gates: Gates,
+    /// auxiliary datasets joined onto the rows of this one
+    #[serde(default)]
+    pub joins: Vec<Join>,
+    /// path of the binary snapshot the dataset is persisted to and restored from on startup
+    pub snapshot: Option<String>,
+    /// number of generations the changes feed reaches back
+    #[serde(default = "default_keep_changes")]
+    pub keep_changes: usize,
     pub uri: String,
+    /// a single character, or `auto` to sniff it from the data
+    #[serde(default = "default_delimiter")]
     pub delimiter: String,
+    /// how the csv is read apart from its `delimiter`
+    #[serde(default)]
+    pub dialect: Dialect,
     pub username: Option<String>,
     pub password: Option<String>,
+    /// sent as `Authorization: Bearer <token>`
+    pub bearer_token: Option<String>,
+    /// further headers sent along with every request, e.g. an `X-Api-Key`
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    pub tls: Option<Tls>,
+    #[serde(default)]
+    pub http: Http,
+    /// credentials and endpoint for `s3://` uris
+    pub s3: Option<S3>,
+    pub refresh: Option<Refresh>,
+}
+
+/// Shown instead of secrets when settings are logged
+const REDACTED: &str = "<redacted>";
+
+fn redact(secret: &Option<String>) -> Option<&'static str> {
+    secret.as_ref().map(|_| REDACTED)
+}
+
+impl fmt::Debug for Csv {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // header values are usually api keys
+        let headers: BTreeMap<&String, &str> =
+            self.headers.keys().map(|name| (name, REDACTED)).collect();
+        f.debug_struct("Csv")
+            .field("name", &self.name)
+            .field("key", &self.key)
+            .field("mode", &self.mode)
+            .field("format", &self.format)
+            .field("encoding", &self.encoding)
+            .field("spreadsheet", &self.spreadsheet)
+            .field("duplicates", &self.duplicates)
+            .field("schema", &self.schema)
+            .field("gates", &self.gates)
+            .field("joins", &self.joins)
+            .field("snapshot", &self.snapshot)
+            .field("keep_changes", &self.keep_changes)
+            .field("uri", &self.uri)
+            .field("delimiter", &self.delimiter)
+            .field("dialect", &self.dialect)
+            .field("username", &self.username)
+            .field("password", &redact(&self.password))
+            .field("bearer_token", &redact(&self.bearer_token))
+            .field("headers", &headers)
+            .field("tls", &self.tls)
+            .field("http", &self.http)
+            .field("s3", &self.s3)
+            .field("refresh", &self.refresh)
+            .finish()
+    }
+}
+
+/// TLS settings for fetching remote sources
+#[derive(Clone, Deserialize)]
+pub struct Tls {
+    /// PEM file of a CA certificate to trust, e.g. of an internal PKI
+    pub ca: Option<String>,
+    /// PKCS #12 file holding the client certificate and key for mTLS
+    pub identity: Option<String>,
+    /// password of the `identity` file
+    #[serde(default)]
+    pub identity_password: String,
+}
+
+impl fmt::Debug for Tls {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let password = if self.identity_password.is_empty() {
+            ""
+        } else {
+            REDACTED
+        };
+        f.debug_struct("Tls")
+            .field("ca", &self.ca)
+            .field("identity", &self.identity)
+            .field("identity_password", &password)
+            .finish()
+    }
+}
+
+/// Settings of an S3-compatible object storage. Without keys the credentials are taken from the
+/// usual `AWS_*` environment variables or profile files.
+#[derive(Clone, Deserialize)]
+pub struct S3 {
+    pub access_key: Option<String>,
+    pub secret_key: Option<String>,
+    /// endpoint of e.g. a MinIO server, defaults to AWS
+    pub endpoint: Option<String>,
+    #[serde(default = "default_region")]
+    pub region: String,
+}
+
+impl fmt::Debug for S3 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("S3")
+            .field("access_key", &self.access_key)
+            .field("secret_key", &redact(&self.secret_key))
+            .field("endpoint", &self.endpoint)
+            .field("region", &self.region)
+            .finish()
+    }
+}
+
+fn default_region() -> String {
+    "us-east-1".into()
+}
+
+impl std::default::Default for S3 {
+    fn default() -> Self {
+        S3 {
+            access_key: None,
+            secret_key: None,
+            endpoint: None,
+            region: default_region(),
+        }
+    }
+}
+
+/// Timeouts and retries for fetching remote sources
+#[derive(Clone, Debug, Deserialize)]
+pub struct Http {
+    /// seconds to wait for a connection
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout: u64,
+    /// seconds to wait for each read from the connection
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    /// how often a request failing with a network error or a 5xx is retried
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    /// milliseconds to wait before the first retry, doubled with every further retry
+    #[serde(default = "default_backoff")]
+    pub backoff: u64,
+}
+
+fn default_connect_timeout() -> u64 {
+    10
+}
+
+fn default_timeout() -> u64 {
+    60
+}
+
+fn default_retries() -> u32 {
+    3
+}
+
+fn default_backoff() -> u64 {
+    500
+}
+
+impl std::default::Default for Http {
+    fn default() -> Self {
+        Http {
+            connect_timeout: default_connect_timeout(),
+            timeout: default_timeout(),
+            retries: default_retries(),
+            backoff: default_backoff(),
+        }
+    }
+}
+
+/// Where to find the table in a spreadsheet
+#[derive(Clone, Debug, Deserialize)]
+pub struct Spreadsheet {
+    /// name of the sheet, defaults to the first one
+    pub sheet: Option<String>,
+    /// number of the row holding the header, starting at 1
+    #[serde(default = "default_header_row")]
+    pub header_row: usize,
 }
 
+fn default_header_row() -> usize {
+    1
+}
+
+impl std::default::Default for Spreadsheet {
+    fn default() -> Self {
+        Spreadsheet {
+            sheet: None,
+            header_row: default_header_row(),
+        }
+    }
+}
+
+/// Schedule for re-loading the csv in the background. Either a fixed `interval` in seconds or a
+/// `cron` expression (with a leading seconds field, e.g. `0 */15 * * * *`) may be given.
 #[derive(Clone, Debug, Deserialize)]
+pub struct Refresh {
+    pub interval: Option<u64>,
+    pub cron: Option<String>,
+}
+
+#[derive(Clone, Deserialize)]
 pub struct Secrets {
     pub app: String,
     pub csrf: String,
     pub jwt: String,
 }
 
+impl fmt::Debug for Secrets {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Secrets")
+            .field("app", &REDACTED)
+            .field("csrf", &REDACTED)
+            .field("jwt", &REDACTED)
+            .finish()
+    }
+}
+
+/// How rows are deserialized
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// strictly into the `Product` struct
+    Product,
+    /// into a `Record` holding whatever columns the header row defines
+    Dynamic,
+}
+
+impl std::default::Default for Mode {
+    fn default() -> Self {
+        Mode::Product
+    }
+}
+
+/// Path prefixes taken by other routes, which can't be used as dataset names
+const RESERVED_NAMES: &[&str] = &["", "admin", "auth"];
+
+fn default_name() -> String {
+    "products".into()
+}
+
+fn default_keep_changes() -> usize {
+    100
+}
+
+fn default_delimiter() -> String {
+    ",".into()
+}
+
+/// What to do with a row whose key is taken already. Either way it is listed in the report.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Duplicates {
+    /// the row read first is kept
+    First,
+    /// the row read last replaces earlier ones
+    Last,
+    /// the row is rejected, the row read first is kept
+    Reject,
+    /// the whole load fails
+    Fail,
+    /// non-empty fields of the row replace the ones of the earlier row
+    Merge,
+}
+
+impl std::default::Default for Duplicates {
+    fn default() -> Self {
+        Duplicates::Last
+    }
+}
+
+/// Either a single key column, `key: sku`, or a composite key, `key: [country, sku]`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum KeyColumns {
+    Single(String),
+    Composite(Vec<String>),
+}
+
+impl KeyColumns {
+    pub fn columns(&self) -> Vec<&str> {
+        match self {
+            KeyColumns::Single(column) => vec![column.as_str()],
+            KeyColumns::Composite(columns) => columns.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+fn default_key() -> KeyColumns {
+    KeyColumns::Single("id".into())
+}
+
+impl Csv {
+    /// The path of the csv if `uri` points to a local file
+    pub fn local_path(&self) -> Option<&Path> {
+        source::local_path(&self.uri)
+    }
+
+    /// Hash of the settings the parsed rows depend on, i.e. where they come from and how they are
+    /// read and keyed. A snapshot written with different ones is not served.
+    pub fn fingerprint(&self) -> u64 {
+        // the schema is sorted, `HashMap`s are iterated in random order
+        let schema: Option<BTreeMap<&String, _>> = self.schema.as_ref().map(|s| s.iter().collect());
+        let settings = format!(
+            "{} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {} {:?} {:?}",
+            self.uri,
+            self.key,
+            self.mode,
+            self.format,
+            self.encoding,
+            self.spreadsheet,
+            self.duplicates,
+            schema,
+            self.delimiter,
+            self.dialect,
+            self.joins
+        );
+        let mut hasher = DefaultHasher::new();
+        settings.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if RESERVED_NAMES.contains(&self.name.as_str()) {
+            return Err(ConfigError::Message(format!(
+                "Dataset name {} is reserved",
+                self.name
+            )));
+        }
+
+        let columns = self.key.columns();
+        if columns.is_empty() || columns.iter().any(|column| column.is_empty()) {
+            return Err(ConfigError::Message(format!(
+                "Dataset {} needs at least one non-empty key column",
+                self.name
+            )));
+        }
+        if self.bearer_token.is_some() && self.username.is_some() {
+            return Err(ConfigError::Message(format!(
+                "Dataset {} can use either basic auth or a bearer token, not both",
+                self.name
+            )));
+        }
+
+        if !self.format.is_available() {
+            return Err(ConfigError::Message(format!(
+                "Dataset {}: format {:?} is not supported by this build",
+                self.name, self.format
+            )));
+        }
+        self.dialect.validate(&self.delimiter).map_err(|e| {
+            ConfigError::Message(format!("Dataset {}, dialect: {}", self.name, e))
+        })?;
+        if let Some(label) = &self.encoding {
+            encoding::validate(label)
+                .map_err(|e| ConfigError::Message(format!("Dataset {}: {}", self.name, e)))?;
+        }
+        if self.spreadsheet.header_row == 0 {
+            return Err(ConfigError::Message(format!(
+                "Dataset {}, spreadsheet: header_row starts at 1",
+                self.name
+            )));
+        }
+
+        self.gates.validate().map_err(|e| {
+            ConfigError::Message(format!("Dataset {}, gates: {}", self.name, e))
+        })?;
+
+        if let Some(schema) = &self.schema {
+            if self.mode != Mode::Dynamic {
+                return Err(ConfigError::Message(format!(
+                    "Dataset {} has a schema, which requires mode dynamic",
+                    self.name
+                )));
+            }
+            for (name, column) in schema {
+                column.validate().map_err(|e| {
+                    ConfigError::Message(format!(
+                        "Dataset {}, column {}: {}",
+                        self.name, name, e
+                    ))
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct Settings {
     pub default: Default,
-    pub csv: Csv,
+    /// a single dataset, kept for backwards compatibility
+    pub csv: Option<Csv>,
+    #[serde(default)]
+    pub datasets: Vec<Csv>,
     pub secrets: Secrets,
 }
 
@@ -89,12 +512,17 @@ impl Settings {
             s.set("default.port", cli.port.unwrap())?;
         }
 
-        if cli.file.is_some() {
-            s.set("csv.file", cli.file.unwrap())?;
-        }
-
-        if cli.delimiter.is_some() {
-            s.set("csv.delimiter", cli.delimiter.unwrap())?;
+        // the csv overrides only apply to the single `csv` section, setting them without one
+        // would leave a partial section behind
+        if s.get_table("csv").is_ok() {
+            if let Some(file) = cli.file {
+                s.set("csv.uri", file)?;
+            }
+            if let Some(delimiter) = cli.delimiter {
+                s.set("csv.delimiter", delimiter)?;
+            }
+        } else if cli.file.is_some() || cli.delimiter.is_some() {
+            eprintln!("Ignoring --file and --delimiter, there is no csv section to apply them to");
         }
 
         // populate the `APP_SECRETS_JWT` env var so we can get in the `decode_token` fn
@@ -105,7 +533,61 @@ impl Settings {
         s.try_into()
     }
 
-    pub fn is_local(&self) -> bool {
-        Path::new(&self.csv.uri).exists()
+    /// All configured datasets, i.e. the `datasets` list plus the single `csv` section if given
+    pub fn datasets(&self) -> Result<Vec<Csv>, ConfigError> {
+        let mut datasets: Vec<Csv> = self.csv.iter().chain(&self.datasets).cloned().collect();
+
+        if datasets.is_empty() {
+            return Err(ConfigError::Message(
+                "Either csv or datasets needs to be configured".into(),
+            ));
+        }
+
+        for dataset in &datasets {
+            dataset.validate()?;
+        }
+
+        datasets.sort_by(|a, b| a.name.cmp(&b.name));
+        if let Some(pair) = datasets.windows(2).find(|pair| pair[0].name == pair[1].name) {
+            return Err(ConfigError::Message(format!(
+                "Dataset {} is configured more than once",
+                pair[0].name
+            )));
+        }
+
+        for dataset in &datasets {
+            for join in &dataset.joins {
+                join.validate(dataset, &datasets).map_err(|e| {
+                    ConfigError::Message(format!(
+                        "Dataset {}, join {}: {}",
+                        dataset.name, join.dataset, e
+                    ))
+                })?;
+            }
+        }
+        Ok(datasets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn redacts_secrets_when_logged() {
+        let source: Csv = serde_json::from_value(json!({
+            "uri": "https://example.com/data.csv",
+            "password": "hunter2-password",
+            "bearer_token": "hunter2-token",
+            "headers": { "X-Api-Key": "hunter2-key" },
+            "tls": { "identity": "client.p12", "identity_password": "hunter2-identity" },
+            "s3": { "access_key": "AKIA", "secret_key": "hunter2-s3" },
+        }))
+        .unwrap();
+        let logged = format!("{:?}", source);
+        assert!(!logged.contains("hunter2"), "{}", logged);
+        assert!(logged.contains("X-Api-Key"));
+        assert!(logged.contains("AKIA"));
     }
 }
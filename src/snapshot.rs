@@ -0,0 +1,114 @@
+//! Module for persisting datasets on disk as compact binary (CBOR) snapshots
+//!
+//! On startup a dataset is served from its snapshot right away, while the fresh data is fetched
+//! in the background. This also keeps us online if the source happens to be down.
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use serde::{Deserialize, Serialize};
+use serde_cbor;
+
+use crate::error::Error;
+use crate::model::{Key, Record};
+use crate::report::ImportReport;
+use crate::source::Validators;
+
+/// Bump this whenever the layout of a snapshot changes, older snapshots are ignored then
+const VERSION: u32 = 1;
+
+/// Numbers the temporary files of the snapshots written by this process
+static TMP: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Deserialize)]
+pub struct Snapshot {
+    version: u32,
+    /// `Csv::fingerprint` of the settings the snapshot was written with
+    fingerprint: u64,
+    pub map: HashMap<Key, Record>,
+    pub report: ImportReport,
+    pub validators: Validators,
+    /// epoch and generation of the changes feed, see `changes`
+    pub epoch: u64,
+    pub generation: u64,
+}
+
+/// Borrowed counterpart of `Snapshot`, so writing one doesn't need to clone the whole map
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    version: u32,
+    fingerprint: u64,
+    map: &'a HashMap<Key, Record>,
+    report: &'a ImportReport,
+    validators: &'a Validators,
+    epoch: u64,
+    generation: u64,
+}
+
+/// Read the snapshot at `path`. Returns `None` if there is none, or it was written by an
+/// incompatible version. Fails if it was written with other settings than the given
+/// `fingerprint`, its rows may be keyed or converted differently.
+pub fn read(path: &str, fingerprint: u64) -> Result<Option<Snapshot>, Error> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let snapshot: Snapshot = serde_cbor::from_reader(BufReader::new(file))?;
+    if snapshot.version != VERSION {
+        return Ok(None);
+    }
+    if snapshot.fingerprint != fingerprint {
+        return Err(Error::Other(
+            "it was written with other settings of the dataset".into(),
+        ));
+    }
+    Ok(Some(snapshot))
+}
+
+/// Write a snapshot to `path`. The snapshot is written to a temporary file of its own first and
+/// renamed into place, so neither a crash nor another writer leaves a half written snapshot
+/// behind.
+pub fn write(
+    path: &str,
+    fingerprint: u64,
+    map: &HashMap<Key, Record>,
+    report: &ImportReport,
+    validators: &Validators,
+    epoch: u64,
+    generation: u64,
+) -> Result<(), Error> {
+    let snapshot = SnapshotRef {
+        version: VERSION,
+        fingerprint,
+        map,
+        report,
+        validators,
+        epoch,
+        generation,
+    };
+
+    let tmp = format!(
+        "{}.{}-{}.tmp",
+        path,
+        process::id(),
+        TMP.fetch_add(1, Ordering::Relaxed)
+    );
+    let written = File::create(&tmp)
+        .map_err(Error::from)
+        .and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            serde_cbor::to_writer(&mut writer, &snapshot)?;
+            writer.flush()?;
+            Ok(())
+        })
+        .and_then(|()| fs::rename(&tmp, Path::new(path)).map_err(Error::from));
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    written
+}
@@ -0,0 +1,39 @@
+//! A dataset stored in a local file
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use super::{Body, DataSource, Fetched, Validators};
+use crate::compression::Compression;
+use crate::error::Error;
+
+#[derive(Debug)]
+pub struct FileSource {
+    path: PathBuf,
+}
+
+impl FileSource {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        FileSource {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl DataSource for FileSource {
+    fn open(&self, _validators: &Validators) -> Result<Fetched<Vec<Body>>, Error> {
+        Ok(Fetched::Modified(
+            vec![open_file(&self.path)?],
+            Validators::default(),
+        ))
+    }
+}
+
+/// Open a local file, taking the compression from its extension
+pub fn open_file(path: &Path) -> Result<Body, Error> {
+    let file = File::open(path)?;
+    Ok(Body {
+        name: path.display().to_string(),
+        rdr: Box::new(file),
+        compression: path.to_str().and_then(Compression::from_path),
+    })
+}
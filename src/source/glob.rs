@@ -0,0 +1,59 @@
+//! A dataset split across several local files, matched by a glob pattern or a directory
+use std::path::{Path, PathBuf};
+
+use super::file::open_file;
+use super::{Body, DataSource, Fetched, Validators};
+use crate::error::Error;
+
+/// The matching files are read in lexical order of their paths, so the outcome of merging them
+/// is deterministic
+#[derive(Debug)]
+pub struct GlobSource {
+    pattern: String,
+}
+
+impl GlobSource {
+    /// Files matching a pattern like `/data/products_part_*.csv`
+    pub fn new(pattern: &str) -> Self {
+        GlobSource {
+            pattern: pattern.into(),
+        }
+    }
+
+    /// All files in the directory `path`, apart from hidden ones
+    pub fn directory(path: &Path) -> Self {
+        // the path itself may contain characters with a special meaning in patterns
+        let escaped = ::glob::Pattern::escape(&path.to_string_lossy());
+        GlobSource::new(&Path::new(&escaped).join("[!.]*").to_string_lossy())
+    }
+
+    fn paths(&self) -> Result<Vec<PathBuf>, Error> {
+        let entries = ::glob::glob(&self.pattern)
+            .map_err(|e| Error::Other(format!("Invalid glob {}: {}", self.pattern, e)))?;
+
+        let mut paths = Vec::new();
+        for entry in entries {
+            let path = entry.map_err(|e| e.into_error())?;
+            if path.is_file() {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+}
+
+impl DataSource for GlobSource {
+    fn open(&self, _validators: &Validators) -> Result<Fetched<Vec<Body>>, Error> {
+        let paths = self.paths()?;
+        if paths.is_empty() {
+            return Err(Error::Other(format!("No files match {}", self.pattern)));
+        }
+
+        let bodies = paths
+            .iter()
+            .map(|path| open_file(path))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Fetched::Modified(bodies, Validators::default()))
+    }
+}
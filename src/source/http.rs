@@ -0,0 +1,243 @@
+//! A dataset fetched from an external service over HTTP(S)
+use std::error::Error as StdError;
+use std::fs;
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use rand::Rng;
+use reqwest;
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, ETAG,
+    IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+};
+use reqwest::StatusCode;
+
+use super::{Body, DataSource, Fetched, Validators};
+use crate::compression::Compression;
+use crate::error::Error;
+use crate::settings::Csv;
+
+/// The client is built once, so connections can be reused between reloads
+#[derive(Debug)]
+pub struct HttpSource {
+    source: Csv,
+    client: reqwest::Client,
+}
+
+impl HttpSource {
+    pub fn new(source: Csv) -> Result<Self, Error> {
+        let client = client(&source)?;
+        Ok(HttpSource { source, client })
+    }
+}
+
+impl DataSource for HttpSource {
+    fn open(&self, validators: &Validators) -> Result<Fetched<Vec<Body>>, Error> {
+        match fetch_data(&self.client, &self.source, validators)? {
+            Fetched::Modified(resp, next) => {
+                let header = |name: HeaderName| {
+                    resp.headers()
+                        .get(name)
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(Compression::from_header)
+                };
+                let compression = header(CONTENT_ENCODING)
+                    .or_else(|| header(CONTENT_TYPE))
+                    .or_else(|| Compression::from_path(&self.source.uri));
+                let body = Body {
+                    name: self.source.uri.clone(),
+                    rdr: Box::new(resp),
+                    compression,
+                };
+                Ok(Fetched::Modified(vec![body], next))
+            }
+            Fetched::NotModified => Ok(Fetched::NotModified),
+        }
+    }
+
+    fn is_remote(&self) -> bool {
+        true
+    }
+}
+
+/// Fetch csv data from an external service. The returned `Response` streams the body.
+///
+/// Network errors and `5xx` responses are retried with exponential backoff, according to the
+/// `http` settings of the `source`.
+pub fn fetch_data(
+    client: &reqwest::Client,
+    source: &Csv,
+    validators: &Validators,
+) -> Result<Fetched<reqwest::Response>, Error> {
+    println!("Fetching data from {}", source.uri);
+
+    let mut attempt = 0;
+    let resp = loop {
+        let result = request(client, source, validators)?.send();
+        let retry = match &result {
+            Ok(resp) => resp.status().is_server_error(),
+            Err(e) => is_transient(e),
+        };
+        if !retry || attempt >= source.http.retries {
+            break result.map_err(|e| {
+                if e.is_timeout() {
+                    Error::Timeout(e.to_string())
+                } else {
+                    Error::Reqwest(e)
+                }
+            })?;
+        }
+
+        let delay = backoff(source.http.backoff, attempt);
+        match &result {
+            Ok(resp) => eprintln!("{} from {}, retrying in {:?}", resp.status(), source.uri, delay),
+            Err(e) => eprintln!("{}, retrying in {:?}", e, delay),
+        }
+        thread::sleep(delay);
+        attempt += 1;
+    };
+
+    if resp.status() == StatusCode::NOT_MODIFIED {
+        println!("{} not modified", source.uri);
+        return Ok(Fetched::NotModified);
+    }
+    if !resp.status().is_success() {
+        return Err(Error::HttpStatus(resp.status()));
+    }
+
+    let header = |name: HeaderName| {
+        resp.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from)
+    };
+    let next = Validators {
+        etag: header(ETAG),
+        last_modified: header(LAST_MODIFIED),
+        hash: None,
+    };
+    Ok(Fetched::Modified(resp, next))
+}
+
+/// Build the client for `source`, with its timeouts, custom headers and TLS settings
+///
+/// reqwest would decompress gzip bodies itself and drop their `Content-Encoding`, leaving us to
+/// guess from the uri, e.g. `.csv.gz`, and decompress plain csv a second time. So we ask for gzip
+/// ourselves and leave the decoding to `decompress`.
+pub fn client(source: &Csv) -> Result<reqwest::Client, Error> {
+    let mut headers = HeaderMap::new();
+    headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip"));
+    for (name, value) in &source.headers {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|e| Error::Other(format!("Invalid header name {}: {}", name, e)))?;
+        let value = HeaderValue::from_str(value)
+            .map_err(|e| Error::Other(format!("Invalid value of header {}: {}", name, e)))?;
+        headers.insert(name, value);
+    }
+
+    let mut builder = reqwest::Client::builder()
+        .gzip(false)
+        .connect_timeout(Duration::from_secs(source.http.connect_timeout))
+        .timeout(Duration::from_secs(source.http.timeout))
+        .default_headers(headers);
+
+    if let Some(tls) = &source.tls {
+        if let Some(ca) = &tls.ca {
+            let pem = fs::read(ca)?;
+            builder = builder.add_root_certificate(reqwest::Certificate::from_pem(&pem)?);
+        }
+        if let Some(identity) = &tls.identity {
+            let der = fs::read(identity)?;
+            builder = builder.identity(reqwest::Identity::from_pkcs12_der(
+                &der,
+                &tls.identity_password,
+            )?);
+        }
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Build the request for `source`, conditional on the `validators` of the previous response
+///
+/// The `ETag` and `Last-Modified` of the previous response are sent along as `If-None-Match` and
+/// `If-Modified-Since`, so the server can answer with `304 Not Modified` instead of the full body.
+fn request(
+    client: &reqwest::Client,
+    source: &Csv,
+    validators: &Validators,
+) -> Result<reqwest::RequestBuilder, Error> {
+    let mut request = match &source.username {
+        Some(username) => {
+            if let Some(password) = &source.password {
+                client
+                    .get(&source.uri)
+                    .basic_auth(username, Some(password))
+            } else {
+                return Err(Error::Other("Need password for Basic Auth".into()));
+            }
+        }
+        None => client.get(&source.uri),
+    };
+
+    if let Some(token) = &source.bearer_token {
+        request = request.bearer_auth(token);
+    }
+
+    if let Some(etag) = &validators.etag {
+        request = request.header(IF_NONE_MATCH, etag.as_str());
+    }
+    if let Some(last_modified) = &validators.last_modified {
+        request = request.header(IF_MODIFIED_SINCE, last_modified.as_str());
+    }
+    Ok(request)
+}
+
+/// Whether a request failed on the way, i.e. timed out or with an IO error like a refused or
+/// reset connection. Anything else, like an invalid url or too many redirects, fails again.
+fn is_transient(e: &reqwest::Error) -> bool {
+    if e.is_timeout() {
+        return true;
+    }
+    let mut cause = e.get_ref().map(|e| e as &(dyn StdError + 'static));
+    while let Some(e) = cause {
+        if e.is::<io::Error>() {
+            return true;
+        }
+        cause = e.source();
+    }
+    false
+}
+
+/// Exponential backoff with jitter, i.e. somewhere between half and all of `base` milliseconds
+/// doubled for every previous `attempt`, capped at a minute
+fn backoff(base: u64, attempt: u32) -> Duration {
+    let delay = base.saturating_mul(1 << attempt.min(16)).min(60_000).max(1);
+    Duration::from_millis(delay / 2 + rand::thread_rng().gen_range(0, delay / 2 + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn retries_only_transient_errors() {
+        // a port nobody listens on anymore refuses the connection
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let client = reqwest::Client::new();
+        let refused = client
+            .get(&format!("http://127.0.0.1:{}/data.csv", port))
+            .send()
+            .unwrap_err();
+        assert!(is_transient(&refused));
+
+        let invalid = client.get("http://").send().unwrap_err();
+        assert!(!is_transient(&invalid));
+    }
+}
@@ -0,0 +1,204 @@
+//! Module holding the sources the raw csv bytes are loaded from
+//!
+//! The source of a dataset is selected by the scheme of its `uri`:
+//!
+//! - no scheme or `file://`: a local file
+//! - `http://` and `https://`: a remote file, see `HttpSource`
+//! - `s3://bucket/key`: an object in an S3-compatible storage, see `S3Source`, if built with the
+//!   `s3` feature
+//! - `glob:pattern` or a directory: several local files merged into one dataset, see `GlobSource`
+//! - `stdin:`: whatever is piped into the process, read once on startup
+//!
+//! Library users can provide further sources by implementing `DataSource` and either handing it
+//! to `Dataset::open_with`, or by `register`ing a factory for their own scheme.
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+use crate::compression::Compression;
+use crate::error::Error;
+use crate::settings::Csv;
+
+mod file;
+mod glob;
+pub mod http;
+#[cfg(feature = "s3")]
+mod s3;
+mod stdin;
+
+pub use self::file::FileSource;
+pub use self::glob::GlobSource;
+pub use self::http::HttpSource;
+#[cfg(feature = "s3")]
+pub use self::s3::S3Source;
+pub use self::stdin::StdinSource;
+
+/// Something the raw bytes of a dataset can be loaded from
+pub trait DataSource: fmt::Debug + Send + Sync {
+    /// Open the data for reading, unless it did not change according to the `validators` of the
+    /// previous load. Nothing should be read yet, the returned `Body`s are streamed while parsing.
+    ///
+    /// Most sources consist of a single `Body`. Datasets split into several parts, each with its
+    /// own header row, return one per part, in the order they are to be merged in.
+    fn open(&self, validators: &Validators) -> Result<Fetched<Vec<Body>>, Error>;
+
+    /// Whether the data is fetched over the network, which tells errors decoding a body apart
+    /// from local IO errors
+    fn is_remote(&self) -> bool {
+        false
+    }
+}
+
+/// The opened data along with what the source knows about its compression
+pub struct Body {
+    /// file name or uri, used in the `ImportReport`
+    pub name: String,
+    pub rdr: Box<dyn Read>,
+    /// `None` has the compression sniffed from the data
+    pub compression: Option<Compression>,
+}
+
+/// What we remember about the last successful load to tell whether the source changed since
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub hash: Option<u64>,
+}
+
+/// Result of retrieving data that may not have changed since the last time
+#[derive(Debug)]
+pub enum Fetched<T> {
+    NotModified,
+    Modified(T, Validators),
+}
+
+/// Builds the `DataSource` of a dataset
+pub type Factory = fn(&Csv) -> Result<Arc<dyn DataSource>, Error>;
+
+lazy_static! {
+    static ref FACTORIES: RwLock<HashMap<String, Factory>> = RwLock::new(HashMap::new());
+}
+
+/// Register a `factory` for datasets whose `uri` has the given `scheme`. This takes precedence
+/// over the built-in sources.
+pub fn register(scheme: &str, factory: Factory) {
+    FACTORIES
+        .write()
+        .unwrap()
+        .insert(scheme.to_lowercase(), factory);
+}
+
+/// Select the `DataSource` for a dataset by the scheme of its `uri`
+pub fn for_uri(source: &Csv) -> Result<Arc<dyn DataSource>, Error> {
+    let scheme = scheme(&source.uri).map(str::to_lowercase);
+
+    if let Some(scheme) = &scheme {
+        if let Some(factory) = FACTORIES.read().unwrap().get(scheme) {
+            return factory(source);
+        }
+    }
+
+    match scheme.as_ref().map(String::as_str) {
+        None | Some("file") => match local_path(&source.uri) {
+            Some(path) if path.is_dir() => Ok(Arc::new(GlobSource::directory(path))),
+            Some(path) => Ok(Arc::new(FileSource::new(path))),
+            None => Err(Error::Other(format!("Invalid file uri {}", source.uri))),
+        },
+        Some("glob") => Ok(Arc::new(GlobSource::new(&source.uri["glob:".len()..]))),
+        Some("http") | Some("https") => Ok(Arc::new(HttpSource::new(source.clone())?)),
+        #[cfg(feature = "s3")]
+        Some("s3") => Ok(Arc::new(S3Source::new(source)?)),
+        Some("stdin") => Ok(Arc::new(StdinSource::new())),
+        Some(scheme) => Err(Error::Other(format!(
+            "Unsupported scheme {} of {}",
+            scheme, source.uri
+        ))),
+    }
+}
+
+/// The scheme of `uri`, if it has one. Plain paths, including Windows paths like `C:\data.csv`,
+/// have none.
+pub fn scheme(uri: &str) -> Option<&str> {
+    let scheme = &uri[..uri.find(':')?];
+    let mut chars = scheme.chars();
+    let valid = scheme.len() > 1
+        && chars.next().map_or(false, |c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.');
+    if valid {
+        Some(scheme)
+    } else {
+        None
+    }
+}
+
+/// The path of a local file `uri`, i.e. one without scheme or with the `file://` scheme
+pub fn local_path(uri: &str) -> Option<&Path> {
+    match scheme(uri) {
+        None => Some(Path::new(uri)),
+        Some(scheme) if scheme.eq_ignore_ascii_case("file") => {
+            let path = &uri[scheme.len() + 1..];
+            Some(Path::new(path.trim_start_matches("//")))
+        }
+        Some(_) => None,
+    }
+}
+
+/// Serves the given bytes, to load datasets in tests
+#[cfg(test)]
+#[derive(Debug)]
+pub struct StaticSource(pub String);
+
+#[cfg(test)]
+impl DataSource for StaticSource {
+    fn open(&self, _validators: &Validators) -> Result<Fetched<Vec<Body>>, Error> {
+        let body = Body {
+            name: "test.csv".to_string(),
+            rdr: Box::new(std::io::Cursor::new(self.0.clone().into_bytes())),
+            compression: Some(Compression::None),
+        };
+        Ok(Fetched::Modified(vec![body], Validators::default()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takes_the_scheme_of_uris() {
+        assert_eq!(scheme("https://example.com/data.csv"), Some("https"));
+        assert_eq!(scheme("s3://bucket/data.csv"), Some("s3"));
+        assert_eq!(scheme("glob:exports/*.csv"), Some("glob"));
+        assert_eq!(scheme("stdin:"), Some("stdin"));
+        assert_eq!(scheme("svn+ssh://host/data.csv"), Some("svn+ssh"));
+    }
+
+    #[test]
+    fn has_no_scheme_for_plain_paths() {
+        assert_eq!(scheme("data.csv"), None);
+        assert_eq!(scheme("/srv/data/products.csv"), None);
+        assert_eq!(scheme(r"C:\data.csv"), None);
+        assert_eq!(scheme("./a:b.csv"), None);
+        assert_eq!(scheme(":data.csv"), None);
+        assert_eq!(scheme("1a:data.csv"), None);
+    }
+
+    #[test]
+    fn takes_the_path_of_local_uris() {
+        assert_eq!(local_path("data.csv"), Some(Path::new("data.csv")));
+        assert_eq!(local_path(r"C:\data.csv"), Some(Path::new(r"C:\data.csv")));
+        assert_eq!(
+            local_path("file:///srv/data.csv"),
+            Some(Path::new("/srv/data.csv"))
+        );
+        assert_eq!(local_path("FILE:data.csv"), Some(Path::new("data.csv")));
+        assert_eq!(local_path("https://example.com/data.csv"), None);
+        assert_eq!(local_path("glob:*.csv"), None);
+    }
+}
@@ -0,0 +1,126 @@
+//! A dataset stored in an S3-compatible object storage, e.g. MinIO
+use rusoto_core::credential::{DefaultCredentialsProvider, StaticProvider};
+use rusoto_core::{HttpClient, Region, RusotoError};
+use rusoto_s3::{GetObjectRequest, S3Client, S3};
+
+use super::{Body, DataSource, Fetched, Validators};
+use crate::compression::Compression;
+use crate::error::Error;
+use crate::settings::Csv;
+
+/// Objects are addressed as `s3://bucket/key`. Their `ETag` is used to skip unchanged objects.
+pub struct S3Source {
+    bucket: String,
+    key: String,
+    client: S3Client,
+}
+
+// `S3Client` doesn't implement `Debug`
+impl std::fmt::Debug for S3Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "S3Source {{ bucket: {:?}, key: {:?} }}", self.bucket, self.key)
+    }
+}
+
+impl S3Source {
+    pub fn new(source: &Csv) -> Result<Self, Error> {
+        let (bucket, key) = parse_uri(&source.uri)
+            .ok_or_else(|| Error::Other(format!("Invalid s3 uri {}", source.uri)))?;
+        let settings = source.s3.clone().unwrap_or_default();
+
+        let region = match &settings.endpoint {
+            Some(endpoint) => Region::Custom {
+                name: settings.region.clone(),
+                endpoint: endpoint.clone(),
+            },
+            None => settings
+                .region
+                .parse()
+                .map_err(|e| Error::S3(format!("Invalid region {}: {}", settings.region, e)))?,
+        };
+        let dispatcher = HttpClient::new().map_err(|e| Error::S3(e.to_string()))?;
+
+        let client = match (&settings.access_key, &settings.secret_key) {
+            (Some(access_key), Some(secret_key)) => S3Client::new_with(
+                dispatcher,
+                StaticProvider::new_minimal(access_key.clone(), secret_key.clone()),
+                region,
+            ),
+            // fall back to the environment, profile files and instance metadata
+            _ => S3Client::new_with(
+                dispatcher,
+                DefaultCredentialsProvider::new().map_err(|e| Error::S3(e.to_string()))?,
+                region,
+            ),
+        };
+
+        Ok(S3Source {
+            bucket: bucket.into(),
+            key: key.into(),
+            client,
+        })
+    }
+}
+
+impl DataSource for S3Source {
+    fn open(&self, validators: &Validators) -> Result<Fetched<Vec<Body>>, Error> {
+        println!("Fetching s3://{}/{}", self.bucket, self.key);
+        let request = GetObjectRequest {
+            bucket: self.bucket.clone(),
+            key: self.key.clone(),
+            if_none_match: validators.etag.clone(),
+            ..Default::default()
+        };
+
+        let output = match self.client.get_object(request).sync() {
+            Ok(output) => output,
+            Err(RusotoError::Unknown(ref resp)) if resp.status.as_u16() == 304 => {
+                println!("s3://{}/{} not modified", self.bucket, self.key);
+                return Ok(Fetched::NotModified);
+            }
+            Err(e) => return Err(Error::S3(e.to_string())),
+        };
+
+        let compression = output
+            .content_encoding
+            .as_ref()
+            .and_then(|value| Compression::from_header(value))
+            .or_else(|| {
+                output
+                    .content_type
+                    .as_ref()
+                    .and_then(|value| Compression::from_header(value))
+            })
+            .or_else(|| Compression::from_path(&self.key));
+        let next = Validators {
+            etag: output.e_tag,
+            last_modified: output.last_modified,
+            hash: None,
+        };
+        let body = output
+            .body
+            .ok_or_else(|| Error::S3(format!("s3://{}/{} has no body", self.bucket, self.key)))?;
+
+        Ok(Fetched::Modified(
+            vec![Body {
+                name: format!("s3://{}/{}", self.bucket, self.key),
+                rdr: Box::new(body.into_blocking_read()),
+                compression,
+            }],
+            next,
+        ))
+    }
+
+    fn is_remote(&self) -> bool {
+        true
+    }
+}
+
+/// Split `s3://bucket/key` into bucket and key
+fn parse_uri(uri: &str) -> Option<(&str, &str)> {
+    let path = uri.splitn(2, "://").nth(1)?;
+    let mut parts = path.splitn(2, '/');
+    let bucket = parts.next().filter(|bucket| !bucket.is_empty())?;
+    let key = parts.next().filter(|key| !key.is_empty())?;
+    Some((bucket, key))
+}
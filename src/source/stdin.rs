@@ -0,0 +1,34 @@
+//! A dataset piped into the process
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::{Body, DataSource, Fetched, Validators};
+use crate::error::Error;
+
+/// Standard input can only be read once, every later load reports it as not modified
+#[derive(Debug, Default)]
+pub struct StdinSource {
+    consumed: AtomicBool,
+}
+
+impl StdinSource {
+    pub fn new() -> Self {
+        StdinSource::default()
+    }
+}
+
+impl DataSource for StdinSource {
+    fn open(&self, _validators: &Validators) -> Result<Fetched<Vec<Body>>, Error> {
+        if self.consumed.swap(true, Ordering::SeqCst) {
+            return Ok(Fetched::NotModified);
+        }
+        Ok(Fetched::Modified(
+            vec![Body {
+                name: "stdin".into(),
+                rdr: Box::new(io::stdin()),
+                compression: None,
+            }],
+            Validators::default(),
+        ))
+    }
+}
@@ -0,0 +1,124 @@
+//! Module for hot reloading a local csv file, or a directory of them, whenever it changes on disk
+//!
+//! We watch the parent directory rather than the file itself. Many editors save by writing a
+//! temporary file and renaming it over the original, which would leave a watch on the file
+//! pointing at an inode that no longer exists. A directory dataset is reloaded whenever any of
+//! the files it is merged from, i.e. the ones directly in it apart from hidden ones, changes.
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+
+use notify::{watcher, DebouncedEvent, RecursiveMode, Watcher};
+
+use crate::data::{self, StateType};
+use crate::error::Error;
+use crate::settings::Csv;
+
+/// Time to wait for further events before reloading, so a burst of writes triggers only one reload
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Spawn a thread for every dataset whose `uri` points to a local file or directory, watching it
+/// for changes
+pub fn spawn(state: StateType) -> Result<Vec<thread::JoinHandle<()>>, Error> {
+    let sources: Vec<Csv> = state
+        .lock()
+        .unwrap()
+        .datasets
+        .values()
+        .map(|dataset| dataset.csv.clone())
+        .filter(|source| source.local_path().is_some())
+        .collect();
+
+    let mut handles = Vec::new();
+    for source in sources {
+        handles.push(watch(state.clone(), source)?);
+    }
+    Ok(handles)
+}
+
+fn watch(state: StateType, source: Csv) -> Result<thread::JoinHandle<()>, Error> {
+    let path = match source.local_path() {
+        Some(path) => path.canonicalize()?,
+        None => return Err(Error::Other(format!("Cannot watch {}", source.uri))),
+    };
+    // a directory is watched itself, a file by way of its parent
+    let directory = path.is_dir();
+    let dir = if directory {
+        Some(path.as_path())
+    } else {
+        path.parent()
+    };
+    let dir = match dir {
+        Some(dir) => dir.to_path_buf(),
+        None => return Err(Error::Other(format!("Cannot watch {}", source.uri))),
+    };
+
+    let (tx, rx) = channel();
+    let mut watcher = watcher(tx, DEBOUNCE)?;
+    watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+
+    println!("Watching {} for changes", path.display());
+    let handle = thread::Builder::new()
+        .name(format!("watch-{}", &source.name))
+        .spawn(move || {
+            // the watcher stops as soon as it is dropped, so it has to live in here
+            let _watcher = watcher;
+
+            for event in rx {
+                if touches(&event, &path, directory) {
+                    reload(&state, &source.name, &path);
+                }
+            }
+        })?;
+    Ok(handle)
+}
+
+/// Whether the `event` leaves new contents at `path`, or, if it is a `directory`, changes any of
+/// the files the dataset is merged from
+fn touches(event: &DebouncedEvent, path: &PathBuf, directory: bool) -> bool {
+    let matches = |p: &PathBuf| {
+        if directory {
+            p.parent() == Some(path.as_path()) && !is_hidden(p)
+        } else {
+            p == path
+        }
+    };
+    match event {
+        DebouncedEvent::Create(p) | DebouncedEvent::Write(p) => matches(p),
+        // a file moved out of or removed from a directory leaves the dataset as well
+        DebouncedEvent::Rename(from, to) => matches(to) || (directory && matches(from)),
+        DebouncedEvent::Remove(p) => directory && matches(p),
+        DebouncedEvent::Rescan => true,
+        DebouncedEvent::Error(e, _) => {
+            eprintln!("Watching {} failed: {}", path.display(), e);
+            false
+        }
+        _ => false,
+    }
+}
+
+/// Hidden files, like the swap files of editors, are not part of a directory dataset
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .map_or(false, |name| name.to_string_lossy().starts_with('.'))
+}
+
+fn reload(state: &StateType, name: &str, path: &PathBuf) {
+    match data::reload(state, name) {
+        Ok(Some(reloaded)) => println!(
+            "Reloaded {} rows of {} from {} ({} added, {} removed)",
+            reloaded.rows,
+            name,
+            path.display(),
+            reloaded.added,
+            reloaded.removed
+        ),
+        Ok(None) => println!("{} unchanged, keeping current data", path.display()),
+        Err(e) => eprintln!(
+            "Reloading {} failed, keeping current data: {}",
+            path.display(),
+            e
+        ),
+    }
+}